@@ -0,0 +1,74 @@
+use shared::api::{ClipInfo, UploadOptions, UploadResponse, UsageResponse};
+
+use crate::client::{Clip, ShortClipClient};
+use crate::error::Result;
+
+/// Async version of `ShortClipClient`
+///
+/// Requests run on the blocking thread pool of the current tokio runtime
+#[derive(Clone, Debug)]
+pub struct AsyncShortClipClient {
+    inner: ShortClipClient,
+}
+
+impl AsyncShortClipClient {
+    pub fn new(host: &str, token: &str) -> Result<Self> {
+        Ok(Self {
+            inner: ShortClipClient::new(host, token)?,
+        })
+    }
+
+    /// The blocking client used for the requests
+    pub fn blocking(&self) -> &ShortClipClient {
+        &self.inner
+    }
+
+    pub async fn upload(
+        &self,
+        data: Vec<u8>,
+        content_type: String,
+        options: UploadOptions,
+    ) -> Result<UploadResponse> {
+        self.run(move |c| c.upload(&data, &content_type, &options))
+            .await
+    }
+
+    pub async fn download(&self, link: String) -> Result<Clip> {
+        self.run(move |c| c.download(&link)).await
+    }
+
+    pub async fn download_with_password(&self, link: String, password: String) -> Result<Clip> {
+        self.run(move |c| c.download_with_password(&link, &password))
+            .await
+    }
+
+    pub async fn info(&self, id: String) -> Result<ClipInfo> {
+        self.run(move |c| c.info(&id)).await
+    }
+
+    pub async fn list(&self) -> Result<Vec<ClipInfo>> {
+        self.run(|c| c.list()).await
+    }
+
+    pub async fn delete(&self, id: String) -> Result<()> {
+        self.run(move |c| c.delete(&id)).await
+    }
+
+    pub async fn usage(&self) -> Result<UsageResponse> {
+        self.run(|c| c.usage()).await
+    }
+
+    async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ShortClipClient) -> Result<T> + Send + 'static,
+    {
+        // The agent shares its connection pool between clones
+        let client = self.inner.clone();
+
+        match tokio::task::spawn_blocking(move || f(&client)).await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
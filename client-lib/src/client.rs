@@ -0,0 +1,217 @@
+use std::io::Read;
+use std::time::Duration;
+
+use shared::api::{
+    headers, routes, ClipInfo, ClipList, UploadOptions, UploadResponse, UsageResponse, API_VERSION,
+};
+use url::Url;
+
+use crate::crypto::{decrypt_clip, encrypt_clip};
+use crate::error::{Error, Result};
+
+/// A downloaded clip
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Blocking client for a short_clip server
+#[derive(Clone, Debug)]
+pub struct ShortClipClient {
+    host: Url,
+    token: String,
+    agent: ureq::Agent,
+}
+
+impl ShortClipClient {
+    /// Creates a client for the server at `host`, e.g. `https://clip.example.com`
+    pub fn new(host: &str, token: &str) -> Result<Self> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(60))
+            // Clips containing a url answer with a redirect, which is the clip itself
+            .redirects(0)
+            .build();
+
+        Ok(Self {
+            host: Url::parse(host)?,
+            token: token.to_owned(),
+            agent,
+        })
+    }
+
+    pub fn host(&self) -> &Url {
+        &self.host
+    }
+
+    /// Uploads a clip and returns its link
+    ///
+    /// If `options.encrypted` is set the clip is encrypted before uploading,
+    /// the key is appended to the returned url as fragment
+    pub fn upload(
+        &self,
+        data: &[u8],
+        content_type: &str,
+        options: &UploadOptions,
+    ) -> Result<UploadResponse> {
+        // The key never leaves this machine except as part of the link
+        let (content_type, payload, key) = if options.encrypted {
+            let (payload, key) = encrypt_clip(data, content_type)?;
+            ("application/octet-stream", payload, Some(key))
+        } else {
+            (content_type, data.to_vec(), None)
+        };
+
+        let mut request = self
+            .request("POST", routes::UPLOAD)?
+            .set("Content-Type", content_type);
+        for (name, value) in options.to_headers() {
+            request = request.set(name, &value);
+        }
+
+        let resp = request.send_bytes(&payload)?;
+        let location = resp.header("Location").map(|l| l.to_owned());
+        let body = resp.into_string().map_err(invalid)?;
+
+        // Servers from before the JSON API only answer with the link
+        let mut resp = match serde_json::from_str::<UploadResponse>(&body) {
+            Ok(resp) => resp,
+            Err(e) => location
+                .as_deref()
+                .and_then(legacy_upload_response)
+                .ok_or_else(|| Error::InvalidResponse(Box::new(e)))?,
+        };
+
+        // Fragments aren't sent to the server when the link is opened
+        if let Some(key) = key {
+            resp.url = format!("{}#{key}", resp.url);
+        }
+
+        Ok(resp)
+    }
+
+    /// Downloads a clip by id or link, decrypting it if the link contains a key
+    pub fn download(&self, link: &str) -> Result<Clip> {
+        self.download_with(link, None)
+    }
+
+    /// Downloads a password protected clip
+    pub fn download_with_password(&self, link: &str, password: &str) -> Result<Clip> {
+        self.download_with(link, Some(password))
+    }
+
+    fn download_with(&self, link: &str, password: Option<&str>) -> Result<Clip> {
+        let mut url = match Url::parse(link) {
+            Ok(url) => url,
+            Err(url::ParseError::RelativeUrlWithoutBase) => self.url(&routes::clip(link))?,
+            Err(e) => return Err(e.into()),
+        };
+        let key = url.fragment().map(|k| k.to_owned());
+        url.set_fragment(None);
+
+        let mut request = self
+            .agent
+            .request_url("GET", &url)
+            .set(headers::API_VERSION, &API_VERSION.to_string());
+        // Links to other servers never get our token
+        if url.origin() == self.host.origin() {
+            request = request.set("Authorization", &format!("Bearer {}", self.token));
+        }
+        if let Some(password) = password {
+            request = request.set(headers::PASSWORD, password);
+        }
+
+        let resp = request.call()?;
+        let content_type = resp.content_type().to_owned();
+
+        let mut data = Vec::new();
+        resp.into_reader()
+            .read_to_end(&mut data)
+            .map_err(|e| Error::Transport(Box::new(e)))?;
+
+        match key {
+            Some(key) => {
+                let (content_type, data) = decrypt_clip(&data, &key)?;
+                Ok(Clip { content_type, data })
+            }
+            None => Ok(Clip { content_type, data }),
+        }
+    }
+
+    /// Details of one of your clips
+    pub fn info(&self, id: &str) -> Result<ClipInfo> {
+        let resp = self.request("GET", &routes::clip_info(id))?.call()?;
+
+        resp.into_json().map_err(invalid)
+    }
+
+    /// All of your clips that haven't expired, oldest first
+    pub fn list(&self) -> Result<Vec<ClipInfo>> {
+        let resp = self.request("GET", routes::CLIPS)?.call()?;
+        let list: ClipList = resp.into_json().map_err(invalid)?;
+
+        Ok(list.clips)
+    }
+
+    /// Deletes one of your clips
+    pub fn delete(&self, id: &str) -> Result<()> {
+        self.request("DELETE", &routes::clip(id))?.call()?;
+
+        Ok(())
+    }
+
+    /// Your current consumption and quota
+    pub fn usage(&self) -> Result<UsageResponse> {
+        let resp = self.request("GET", routes::USAGE)?.call()?;
+
+        resp.into_json().map_err(invalid)
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        // Keep a path prefix of the host, e.g. when running behind a reverse proxy
+        let base = self.host.as_str().trim_end_matches('/');
+
+        Ok(Url::parse(&format!("{base}{path}"))?)
+    }
+
+    fn request(&self, method: &str, path: &str) -> Result<ureq::Request> {
+        Ok(self
+            .agent
+            .request_url(method, &self.url(path)?)
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .set(headers::API_VERSION, &API_VERSION.to_string()))
+    }
+}
+
+/// Builds the response of an older server from the `Location` header of the upload
+fn legacy_upload_response(location: &str) -> Option<UploadResponse> {
+    let url = Url::parse(location).ok()?;
+    let id = url.path_segments()?.next_back()?.to_owned();
+    if id.is_empty() {
+        return None;
+    }
+
+    Some(UploadResponse {
+        id,
+        url: location.to_owned(),
+    })
+}
+
+fn invalid(e: std::io::Error) -> Error {
+    Error::InvalidResponse(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::legacy_upload_response;
+
+    #[test]
+    fn legacy_location() {
+        let resp = legacy_upload_response("https://clip.example.com/abc").unwrap();
+        assert_eq!(resp.id, "abc");
+        assert_eq!(resp.url, "https://clip.example.com/abc");
+
+        assert!(legacy_upload_response("https://clip.example.com/").is_none());
+        assert!(legacy_upload_response("not a url").is_none());
+    }
+}
@@ -0,0 +1,101 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::error::{Error, Result};
+
+const NONCE_SIZE: usize = 12;
+
+/// Encrypts a clip with a fresh random key
+///
+/// The content type is encrypted together with the data, so the server
+/// only ever sees `application/octet-stream`. Returns the payload
+/// (`nonce || ciphertext`) and the url safe base64 encoded key
+pub fn encrypt_clip(data: &[u8], content_type: &str) -> Result<(Vec<u8>, String)> {
+    let key = Aes256Gcm::generate_key(OsRng);
+    let nonce = Aes256Gcm::generate_nonce(OsRng);
+
+    let mut plaintext = Vec::with_capacity(content_type.len() + 1 + data.len());
+    plaintext.extend_from_slice(content_type.as_bytes());
+    plaintext.push(b'\n');
+    plaintext.extend_from_slice(data);
+
+    let ciphertext = Aes256Gcm::new(&key)
+        .encrypt(&nonce, plaintext.as_slice())
+        .map_err(|_| crypto_error("Unable to encrypt clip"))?;
+
+    let mut payload = nonce.to_vec();
+    payload.extend_from_slice(&ciphertext);
+
+    Ok((payload, general_purpose::URL_SAFE_NO_PAD.encode(key)))
+}
+
+/// Reverses `encrypt_clip`, returning the content type and the data
+pub fn decrypt_clip(payload: &[u8], key: &str) -> Result<(String, Vec<u8>)> {
+    let key = general_purpose::URL_SAFE_NO_PAD
+        .decode(key)
+        .map_err(|e| Error::Crypto {
+            message: "Invalid key".to_owned(),
+            source: Some(Box::new(e)),
+        })?;
+    if key.len() != 32 {
+        return Err(crypto_error("Invalid key length"));
+    }
+    if payload.len() < NONCE_SIZE {
+        return Err(crypto_error("Encrypted clip too short"));
+    }
+
+    let (nonce, ciphertext) = payload.split_at(NONCE_SIZE);
+    let plaintext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| crypto_error("Unable to decrypt clip, wrong key?"))?;
+
+    let split = plaintext
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or(crypto_error("Decrypted clip has no content type"))?;
+    let content_type =
+        String::from_utf8(plaintext[..split].to_vec()).map_err(|e| Error::Crypto {
+            message: "Decrypted clip has an invalid content type".to_owned(),
+            source: Some(Box::new(e)),
+        })?;
+
+    Ok((content_type, plaintext[split + 1..].to_vec()))
+}
+
+fn crypto_error(msg: &str) -> Error {
+    Error::Crypto {
+        message: msg.to_owned(),
+        source: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let (payload, key) = encrypt_clip(b"hello", "text/plain").unwrap();
+        assert_eq!(payload.len(), NONCE_SIZE + "text/plain\nhello".len() + 16);
+
+        let (content_type, data) = decrypt_clip(&payload, &key).unwrap();
+        assert_eq!(content_type, "text/plain");
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn wrong_or_invalid_keys() {
+        let (payload, _) = encrypt_clip(b"hello", "text/plain").unwrap();
+        let (_, other_key) = encrypt_clip(b"", "text/plain").unwrap();
+
+        assert!(decrypt_clip(&payload, &other_key).is_err());
+        assert!(decrypt_clip(&payload, "c2hvcnQ").is_err());
+        assert!(decrypt_clip(&payload[..NONCE_SIZE - 1], &other_key).is_err());
+
+        let e = decrypt_clip(&payload, "not base64!").unwrap_err();
+        assert!(e.source().is_some());
+    }
+}
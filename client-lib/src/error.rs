@@ -0,0 +1,133 @@
+use std::fmt;
+use std::time::Duration;
+
+use shared::api::{ErrorBody, ErrorCode};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Everything that can go wrong talking to a short_clip server
+#[derive(Debug)]
+pub enum Error {
+    /// The server rejected the request
+    Api {
+        status: u16,
+        code: ErrorCode,
+        message: String,
+        /// How long to wait before trying again, from the `Retry-After` header
+        retry_after: Option<Duration>,
+    },
+    /// The server couldn't be reached or the connection broke
+    Transport(BoxError),
+    /// The server answered with something this client doesn't understand
+    InvalidResponse(BoxError),
+    /// The host or a link isn't a valid url
+    InvalidUrl(url::ParseError),
+    /// Encrypting or decrypting a clip failed
+    Crypto {
+        message: String,
+        source: Option<BoxError>,
+    },
+}
+
+impl Error {
+    /// The API error code, if the server rejected the request
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Api { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Whether retrying the request later might succeed, e.g. when the network is down
+    ///
+    /// Transport errors only count if the connection couldn't be established,
+    /// otherwise the server might have processed the request already
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Transport(e) => e.downcast_ref::<ureq::Transport>().is_some_and(|t| {
+                matches!(
+                    t.kind(),
+                    ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed
+                )
+            }),
+            Error::Api { status, code, .. } => {
+                *status >= 500 || *code == ErrorCode::TooManyRequests
+            }
+            _ => false,
+        }
+    }
+
+    /// How long the server asked to wait before trying again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Api { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Api {
+                status, message, ..
+            } => write!(f, "{message} ({status})"),
+            Error::Transport(e) => write!(f, "Unable to reach server: {e}"),
+            Error::InvalidResponse(e) => write!(f, "Invalid response: {e}"),
+            Error::InvalidUrl(e) => write!(f, "Invalid url: {e}"),
+            Error::Crypto { message, .. } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) | Error::InvalidResponse(e) => Some(e.as_ref()),
+            Error::Crypto {
+                source: Some(e), ..
+            } => Some(e.as_ref()),
+            Error::InvalidUrl(e) => Some(e),
+            Error::Api { .. } | Error::Crypto { source: None, .. } => None,
+        }
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Self {
+        Error::InvalidUrl(e)
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(status, resp) => {
+                let status_text = resp.status_text().to_owned();
+                let retry_after = resp
+                    .header("retry-after")
+                    .and_then(|s| s.trim().parse().ok())
+                    .map(Duration::from_secs);
+
+                // Proxies in front of the server don't answer with an `ErrorBody`
+                match resp.into_json::<ErrorBody>() {
+                    Ok(body) => Error::Api {
+                        status,
+                        code: body.error,
+                        message: body.message,
+                        retry_after,
+                    },
+                    Err(_) => Error::Api {
+                        status,
+                        code: ErrorCode::Unknown,
+                        message: status_text,
+                        retry_after,
+                    },
+                }
+            }
+            ureq::Error::Transport(t) => Error::Transport(Box::new(t)),
+        }
+    }
+}
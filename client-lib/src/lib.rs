@@ -0,0 +1,29 @@
+//! Client for the short_clip API
+//!
+//! ```no_run
+//! use short_clip_lib::{ShortClipClient, UploadOptions};
+//!
+//! let client = ShortClipClient::new("https://clip.example.com", "token")?;
+//! let options = UploadOptions {
+//!     ttl: Some(60 * 60),
+//!     ..Default::default()
+//! };
+//! let uploaded = client.upload(b"hello", "text/plain", &options)?;
+//! println!("{}", uploaded.url);
+//! # Ok::<(), short_clip_lib::Error>(())
+//! ```
+//!
+//! With the `async` feature `AsyncShortClipClient` offers the same methods as futures
+
+#[cfg(feature = "async")]
+mod async_client;
+mod client;
+pub mod crypto;
+mod error;
+
+#[cfg(feature = "async")]
+pub use async_client::AsyncShortClipClient;
+pub use client::{Clip, ShortClipClient};
+pub use error::{Error, Result};
+pub use shared::api::{ClipInfo, ErrorCode, UploadOptions, UploadResponse, Usage, UsageResponse};
+pub use shared::Visibility;
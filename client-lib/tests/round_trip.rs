@@ -0,0 +1,204 @@
+//! Runs the client against a minimal in-process server speaking the short_clip API
+
+use std::collections::HashMap;
+use std::error::Error as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use short_clip_lib::{Error, ErrorCode, ShortClipClient, UploadOptions};
+
+type Clips = Arc<Mutex<HashMap<String, (String, Vec<u8>)>>>;
+
+/// Starts the server, `legacy` servers answer uploads with the bare link like before the JSON API
+fn start_server(legacy: bool) -> (String, Clips) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = format!("http://{}", listener.local_addr().unwrap());
+    let clips = Clips::default();
+
+    let server_host = host.clone();
+    let server_clips = clips.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            handle(stream.unwrap(), &server_host, &server_clips, legacy);
+        }
+    });
+
+    (host, clips)
+}
+
+fn handle(mut stream: TcpStream, host: &str, clips: &Clips, legacy: bool) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap().to_owned();
+    let path = parts.next().unwrap().to_owned();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        match line.trim_end().split_once(": ") {
+            Some((name, value)) => headers.insert(name.to_lowercase(), value.to_owned()),
+            None => break,
+        };
+    }
+    let length = headers
+        .get("content-length")
+        .map_or(0, |l| l.parse().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    let (status, content_type, extra, body) = match (method.as_str(), path.as_str()) {
+        // The connection breaks after the request was sent
+        (_, "/hangup") => return,
+        (_, "/busy") => (
+            "429 Too Many Requests",
+            "application/json".to_owned(),
+            "Retry-After: 7\r\n".to_owned(),
+            br#"{"error":"too_many_requests","message":"Too Many Requests"}"#.to_vec(),
+        ),
+        _ if headers.get("authorization").map(String::as_str) != Some("Bearer token") => (
+            "401 Unauthorized",
+            "application/json".to_owned(),
+            String::new(),
+            br#"{"error":"unauthorized","message":"Unauthorized: invalid token"}"#.to_vec(),
+        ),
+        ("POST", "/") => {
+            let mut clips = clips.lock().unwrap();
+            let id = format!("clip{}", clips.len());
+            let url = format!("{host}/{id}");
+            clips.insert(id.clone(), (headers["content-type"].clone(), body));
+
+            let extra = format!("Location: {url}\r\n");
+            if legacy {
+                (
+                    "201 Created",
+                    "text/plain".to_owned(),
+                    extra,
+                    url.into_bytes(),
+                )
+            } else {
+                let body = format!(r#"{{"id":"{id}","url":"{url}"}}"#);
+                (
+                    "201 Created",
+                    "application/json".to_owned(),
+                    extra,
+                    body.into_bytes(),
+                )
+            }
+        }
+        ("GET", path) => match clips.lock().unwrap().get(&path[1..]) {
+            Some((content_type, data)) => {
+                ("200 OK", content_type.clone(), String::new(), data.clone())
+            }
+            None => (
+                "404 Not Found",
+                "application/json".to_owned(),
+                String::new(),
+                br#"{"error":"not_found","message":"Not found"}"#.to_vec(),
+            ),
+        },
+        _ => panic!("Unexpected request {method} {path}"),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n{extra}\r\n",
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(&body).unwrap();
+}
+
+#[test]
+fn upload_and_download() {
+    let (host, _) = start_server(false);
+    let client = ShortClipClient::new(&host, "token").unwrap();
+
+    let uploaded = client
+        .upload(b"hello", "text/plain", &UploadOptions::default())
+        .unwrap();
+    assert_eq!(uploaded.url, format!("{host}/{}", uploaded.id));
+
+    for link in [uploaded.url.as_str(), uploaded.id.as_str()] {
+        let clip = client.download(link).unwrap();
+        assert_eq!(clip.content_type, "text/plain");
+        assert_eq!(clip.data, b"hello");
+    }
+}
+
+#[test]
+fn encrypted_round_trip() {
+    let (host, clips) = start_server(false);
+    let client = ShortClipClient::new(&host, "token").unwrap();
+    let options = UploadOptions {
+        encrypted: true,
+        ..Default::default()
+    };
+
+    let uploaded = client.upload(b"secret", "text/plain", &options).unwrap();
+    let (link, key) = uploaded.url.split_once('#').unwrap();
+    assert_eq!(link, format!("{host}/{}", uploaded.id));
+    assert!(!key.is_empty());
+
+    // The server neither sees the data nor its type
+    let (content_type, stored) = clips.lock().unwrap()[&uploaded.id].clone();
+    assert_eq!(content_type, "application/octet-stream");
+    assert!(!stored.windows(6).any(|w| w == b"secret"));
+
+    let clip = client.download(&uploaded.url).unwrap();
+    assert_eq!(clip.content_type, "text/plain");
+    assert_eq!(clip.data, b"secret");
+
+    assert!(matches!(client.download(link), Ok(c) if c.data == stored));
+}
+
+#[test]
+fn legacy_server_upload() {
+    let (host, _) = start_server(true);
+    let client = ShortClipClient::new(&host, "token").unwrap();
+
+    let uploaded = client
+        .upload(b"hello", "text/plain", &UploadOptions::default())
+        .unwrap();
+    assert_eq!(uploaded.id, "clip0");
+    assert_eq!(uploaded.url, format!("{host}/clip0"));
+}
+
+#[test]
+fn errors() {
+    let (host, _) = start_server(false);
+
+    let client = ShortClipClient::new(&host, "token").unwrap();
+    let e = client.download("missing").unwrap_err();
+    assert_eq!(e.code(), Some(ErrorCode::NotFound));
+    assert!(!e.is_transient());
+    assert_eq!(e.retry_after(), None);
+
+    let e = client.download("busy").unwrap_err();
+    assert_eq!(e.code(), Some(ErrorCode::TooManyRequests));
+    assert!(e.is_transient());
+    assert_eq!(e.retry_after(), Some(Duration::from_secs(7)));
+
+    // The server may have received the request, so it isn't retried
+    let e = client.download("hangup").unwrap_err();
+    assert!(matches!(e, Error::Transport(_)));
+    assert!(!e.is_transient());
+
+    let client = ShortClipClient::new(&host, "wrong").unwrap();
+    let e = client
+        .upload(b"hello", "text/plain", &UploadOptions::default())
+        .unwrap_err();
+    assert_eq!(e.code(), Some(ErrorCode::Unauthorized));
+
+    // Nothing listens on the discard port
+    let client = ShortClipClient::new("http://127.0.0.1:9", "token").unwrap();
+    let e = client.download("missing").unwrap_err();
+    assert!(matches!(e, Error::Transport(_)));
+    assert!(e.is_transient());
+    assert!(e.source().is_some());
+}
@@ -0,0 +1,199 @@
+use std::str::FromStr;
+
+use serde::de::value::StrDeserializer;
+use serde::{Deserialize, Serialize};
+use shared::api::URI_LIST;
+use short_clip_lib::Visibility;
+
+use crate::cli::UploadArgs;
+use crate::config::UploadPreset;
+use crate::hotkey::Hotkey;
+use crate::sys::clipboard::{read_clipboard, read_selection};
+use crate::sys::screenshot::take_screenshot;
+use crate::upload::upload_content;
+
+/// What the daemon does when a hotkey is pressed
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Uploads the clipboard
+    Upload,
+    /// Uploads the clipboard as private clip
+    UploadPrivate,
+    /// Uploads the clipboard as clip that expires after an hour
+    UploadTemporary,
+    /// Uploads the primary selection, the text that was last selected
+    UploadSelection,
+    /// Takes a screenshot and uploads it
+    Screenshot,
+    /// Uploads the clipboard only if it contains a url
+    Shorten,
+}
+
+/// A hotkey and the action it triggers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Binding {
+    pub keys: Hotkey,
+    #[serde(default = "default_action")]
+    pub action: Action,
+    /// Profile the upload goes to, instead of the default one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Preset applied to the upload
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// Options overriding the preset and the command line
+    #[serde(flatten)]
+    pub options: UploadPreset,
+}
+
+fn default_action() -> Action {
+    Action::Upload
+}
+
+impl Binding {
+    /// The upload options of this binding on top of those given on the command line,
+    /// with the defaults of its action
+    pub fn upload_args(&self, args: &UploadArgs) -> UploadArgs {
+        UploadArgs {
+            profile: self.profile.clone().or(args.profile.clone()),
+            preset: self.preset.clone().or(args.preset.clone()),
+            defaults: self.action.defaults(),
+            overrides: args.overrides.clone().with(&self.options),
+        }
+    }
+
+    pub fn run(&self, args: &UploadArgs) -> Result<(), Box<dyn std::error::Error>> {
+        self.action.upload(&self.upload_args(args))
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    /// Parses the names used in the config file, like `upload_private`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::deserialize(StrDeserializer::<serde::de::value::Error>::new(s))
+            .map_err(|_| format!("Unknown action \"{s}\""))
+    }
+}
+
+impl Action {
+    /// Options the action uploads with unless they are set explicitly
+    pub fn defaults(&self) -> UploadPreset {
+        match self {
+            Action::UploadPrivate => UploadPreset {
+                visibility: Some(Visibility::Private),
+                ..Default::default()
+            },
+            Action::UploadTemporary => UploadPreset {
+                ttl: Some(60 * 60),
+                ..Default::default()
+            },
+            _ => UploadPreset::default(),
+        }
+    }
+
+    /// Runs the action with the options given on the command line
+    pub fn run(&self, args: &UploadArgs) -> Result<(), Box<dyn std::error::Error>> {
+        self.upload(&UploadArgs {
+            defaults: self.defaults(),
+            ..args.clone()
+        })
+    }
+
+    /// Runs the action, `args` already contain its defaults
+    fn upload(&self, args: &UploadArgs) -> Result<(), Box<dyn std::error::Error>> {
+        let content = match self {
+            Action::Upload | Action::UploadPrivate | Action::UploadTemporary => read_clipboard()?,
+            Action::UploadSelection => read_selection()?,
+            Action::Screenshot => take_screenshot()?,
+            Action::Shorten => {
+                let content = read_clipboard()?;
+                if content.content_type != URI_LIST {
+                    return Err("The clipboard doesn't contain a url".into());
+                }
+                content
+            }
+        };
+
+        upload_content(args, content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn binding(json: &str) -> Binding {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn options_are_layered_in_order() {
+        let config: Config = serde_json::from_str(
+            r#"{
+                "visibility": "unlisted",
+                "profiles": {"home": {"host": "h", "token": "t"}},
+                "presets": {"day": {"ttl": "1d"}}
+            }"#,
+        )
+        .unwrap();
+        let profile = config.profile(Some("home")).unwrap();
+        let options = |binding: &Binding, args: &UploadArgs| {
+            config
+                .upload_options(&profile, &binding.upload_args(args))
+                .unwrap()
+        };
+        let no_args = UploadArgs::default();
+        let with_preset = UploadArgs {
+            preset: Some("day".to_owned()),
+            ..Default::default()
+        };
+        let with_ttl = UploadArgs {
+            preset: Some("day".to_owned()),
+            overrides: UploadPreset {
+                ttl: Some(30 * 60),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Action defaults replace the configured defaults, presets replace the action
+        // defaults and the command line replaces the preset
+        let temporary = binding(r#"{"keys": "Ctrl+T", "action": "upload_temporary"}"#);
+        assert_eq!(temporary.upload_args(&no_args).defaults.ttl, Some(60 * 60));
+        assert_eq!(options(&temporary, &no_args).ttl, Some(60 * 60));
+        assert_eq!(options(&temporary, &with_preset).ttl, Some(24 * 60 * 60));
+        assert_eq!(options(&temporary, &with_ttl).ttl, Some(30 * 60));
+        assert_eq!(
+            options(&temporary, &no_args).visibility,
+            Some(Visibility::Unlisted)
+        );
+
+        let temporary =
+            binding(r#"{"keys": "Ctrl+T", "action": "upload_temporary", "preset": "day"}"#);
+        assert_eq!(options(&temporary, &no_args).ttl, Some(24 * 60 * 60));
+
+        // Options of the binding replace everything else
+        let temporary = binding(r#"{"keys": "Ctrl+T", "action": "upload_temporary", "ttl": "2h"}"#);
+        assert_eq!(options(&temporary, &with_ttl).ttl, Some(2 * 60 * 60));
+
+        let private = binding(r#"{"keys": "Ctrl+P", "action": "upload_private"}"#);
+        let resolved = options(&private, &with_preset);
+        assert_eq!(resolved.visibility, Some(Visibility::Private));
+        assert_eq!(resolved.ttl, Some(24 * 60 * 60));
+        let cli_visibility = UploadArgs {
+            overrides: UploadPreset {
+                visibility: Some(Visibility::Public),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            options(&private, &cli_visibility).visibility,
+            Some(Visibility::Public)
+        );
+    }
+}
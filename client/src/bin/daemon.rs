@@ -1,9 +1,59 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+
+use short_clip_client::cli::{UploadArgs, UPLOAD_USAGE};
+use short_clip_client::config::load_config;
+use short_clip_client::notify::notify_failed;
 use short_clip_client::sys;
-use short_clip_client::upload;
-use upload::upload_clipboard;
 
-/// This will create a long running process that grabs the
-/// key combination Ctrl + U and uploads the clipboard
+/// This will create a long running process that grabs the configured hotkeys
+/// and runs their actions, by default Ctrl + U uploads the clipboard
+///
+/// The profile and upload options given as arguments apply to every upload
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    sys::hotkey::create_listener(upload_clipboard)
+    sys::serve_if_requested();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let upload_args = match UploadArgs::parse(&args) {
+        Ok((upload_args, rest)) if rest.is_empty() => upload_args,
+        Ok(_) => usage("Unknown arguments"),
+        Err(e) => usage(&e),
+    };
+
+    let bindings = load_config().bindings()?;
+    let hotkeys: Vec<_> = bindings.iter().map(|b| b.keys).collect();
+
+    // Actions run on their own thread, so retrying an upload doesn't hold up
+    // the event loop. Hotkeys pressed in the meantime run once it is done
+    let (sender, receiver) = mpsc::channel::<usize>();
+    thread::spawn(move || {
+        for index in receiver {
+            let binding = &bindings[index];
+
+            // A failed action shouldn't stop the other hotkeys from working, not even if
+            // it panicked. The panic message was printed by the panic hook already
+            match panic::catch_unwind(AssertUnwindSafe(|| binding.run(&upload_args))) {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    eprintln!("{}: {e}", binding.keys);
+                    notify_failed(e);
+                }
+                Err(_) => {
+                    eprintln!("{}: The action panicked", binding.keys);
+                    notify_failed("The action crashed");
+                }
+            }
+        }
+    });
+
+    sys::hotkey::create_listener(&hotkeys, move |index| {
+        // The thread catches panics of actions, so it runs as long as the listener
+        sender.send(index).expect("The action thread stopped");
+    })
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{error}\n\nUsage: daemon [options]\n\n{UPLOAD_USAGE}");
+    std::process::exit(2);
 }
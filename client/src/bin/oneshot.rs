@@ -1,7 +1,58 @@
+use std::io::Write;
+
+use short_clip_client::actions::Action;
+use short_clip_client::cli::{UploadArgs, ACTIONS_USAGE, UPLOAD_USAGE};
+use short_clip_client::download::download_clip;
+use short_clip_client::notify::notify_failed;
+use short_clip_client::sys;
 use short_clip_client::upload::upload_clipboard;
 
 /// When executed this will upload the current clipboard content
 /// and replace it with a link
+///
+/// `client <action>` runs one of the hotkey actions instead, like `screenshot`
+///
+/// `client get <link>` downloads a clip instead and writes it to stdout,
+/// decrypting it if the link contains a key
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    upload_clipboard()
+    sys::serve_if_requested();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (upload_args, args) = match UploadArgs::parse(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => usage(&e),
+    };
+
+    match args.as_slice() {
+        [] => notify_on_error(upload_clipboard(&upload_args)),
+        [cmd, link] if cmd == "get" => {
+            let content = download_clip(link, upload_args.profile.as_deref())?;
+            std::io::stdout().write_all(&content.data)?;
+
+            Ok(())
+        }
+        [action] => match action.parse::<Action>() {
+            Ok(action) => notify_on_error(action.run(&upload_args)),
+            Err(e) => usage(&e),
+        },
+        _ => usage("Unknown arguments"),
+    }
+}
+
+/// Uploads are usually started from a shortcut without a terminal, so failures are shown as notification
+fn notify_on_error(
+    result: Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Err(e) = &result {
+        notify_failed(e);
+    }
+
+    result
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!(
+        "{error}\n\nUsage: client [options] [action]\n       client [--profile <name>] get <link>\n\n{UPLOAD_USAGE}\n\n{ACTIONS_USAGE}"
+    );
+    std::process::exit(2);
 }
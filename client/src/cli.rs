@@ -0,0 +1,74 @@
+use short_clip_lib::Visibility;
+
+use crate::config::UploadPreset;
+
+/// Upload options given on the command line
+#[derive(Clone, Debug, Default)]
+pub struct UploadArgs {
+    /// Name of a profile from the config file
+    pub profile: Option<String>,
+    /// Name of a preset from the config file
+    pub preset: Option<String>,
+    /// Options of the action, overridden by the preset
+    pub defaults: UploadPreset,
+    /// Options overriding the preset and the defaults
+    pub overrides: UploadPreset,
+}
+
+pub const UPLOAD_USAGE: &str = "Options:
+    --profile <name>      Use a profile from the config file, defaults to $SHORTCLIP_PROFILE
+    --preset <name>       Use a preset from the config file
+    --ttl <duration>      Expire the clip, e.g. 90, 30m, 1h, 7d or 2w
+    --visibility <value>  public, unlisted or private
+    --max-views <n>       Delete the clip after n downloads
+    --encrypt             Encrypt the clip before uploading
+    --no-encrypt          Upload the clip unencrypted";
+
+pub const ACTIONS_USAGE: &str = "Actions:
+    upload                Upload the clipboard, the default
+    upload_private        Upload the clipboard as private clip
+    upload_temporary      Upload the clipboard as clip that expires after an hour
+    upload_selection      Upload the selected text
+    screenshot            Take a screenshot and upload it
+    shorten               Upload the clipboard only if it contains a url";
+
+impl UploadArgs {
+    /// Takes the upload options out of `args`, returning the remaining arguments
+    pub fn parse(args: &[String]) -> Result<(Self, Vec<String>), String> {
+        let mut result = UploadArgs::default();
+        let mut rest = Vec::new();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {arg}"))
+            };
+
+            match arg.as_str() {
+                "--profile" => result.profile = Some(value()?.to_owned()),
+                "--preset" => result.preset = Some(value()?.to_owned()),
+                "--ttl" => {
+                    let v = value()?;
+                    result.overrides.ttl =
+                        Some(shared::parse_duration(v).ok_or(format!("Invalid duration \"{v}\""))?);
+                }
+                "--visibility" => {
+                    result.overrides.visibility = Some(value()?.parse::<Visibility>()?);
+                }
+                "--max-views" => {
+                    let v = value()?;
+                    match v.parse::<u32>() {
+                        Ok(n) if n > 0 => result.overrides.max_views = Some(n),
+                        _ => return Err(format!("Invalid number of views \"{v}\"")),
+                    }
+                }
+                "--encrypt" => result.overrides.encrypt = Some(true),
+                "--no-encrypt" => result.overrides.encrypt = Some(false),
+                _ => rest.push(arg.clone()),
+            }
+        }
+
+        Ok((result, rest))
+    }
+}
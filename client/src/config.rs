@@ -1,11 +1,220 @@
-use std::{fs, path::PathBuf, sync::OnceLock};
+use std::{collections::HashMap, fs, path::PathBuf, sync::OnceLock};
 
 use serde::{Deserialize, Serialize};
+use short_clip_lib::{ShortClipClient, UploadOptions, Visibility};
+
+use crate::actions::{Action, Binding};
+use crate::cli::UploadArgs;
+use crate::hotkey::Hotkey;
+
+/// Environment variable selecting the profile if none is given on the command line
+pub const PROFILE_VAR: &str = "SHORTCLIP_PROFILE";
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
+    /// Server of config files without profiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Named servers
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// Profile used if none is selected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    /// Options used for every upload
+    #[serde(flatten)]
+    pub defaults: UploadPreset,
+    /// Named sets of options, applied on top of the defaults
+    #[serde(default)]
+    pub presets: HashMap<String, UploadPreset>,
+    /// Key combination the daemon uploads the clipboard on, like `Ctrl+Shift+U`
+    ///
+    /// Only used if no `hotkeys` are configured
+    #[serde(default)]
+    pub hotkey: Hotkey,
+    /// Hotkeys of the daemon and the actions they trigger
+    #[serde(default)]
+    pub hotkeys: Vec<Binding>,
+    /// Show a desktop notification after every upload, not supported on Windows
+    #[serde(default = "default_notifications")]
+    pub notifications: bool,
+    /// Also copy links to the primary selection, which is pasted with the middle mouse button
+    #[serde(default)]
+    pub copy_to_primary: bool,
+}
+
+fn default_notifications() -> bool {
+    cfg!(unix)
+}
+
+/// A server together with the token used for it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Profile {
     pub host: String,
     pub token: String,
+    /// Options used for every upload to this server, applied on top of the global defaults
+    #[serde(flatten)]
+    pub defaults: UploadPreset,
+}
+
+impl Profile {
+    /// API client for this server
+    pub fn client(&self) -> Result<ShortClipClient, short_clip_lib::Error> {
+        ShortClipClient::new(&self.host, &self.token)
+    }
+}
+
+impl Config {
+    /// The configured hotkeys, or `hotkey` bound to uploading the clipboard
+    ///
+    /// Fails if the same key combination is bound more than once
+    pub fn bindings(&self) -> Result<Vec<Binding>, String> {
+        if !self.hotkeys.is_empty() {
+            for (i, binding) in self.hotkeys.iter().enumerate() {
+                if self.hotkeys[..i].iter().any(|b| b.keys == binding.keys) {
+                    return Err(format!("{} is bound more than once", binding.keys));
+                }
+            }
+
+            return Ok(self.hotkeys.clone());
+        }
+
+        Ok(vec![Binding {
+            keys: self.hotkey,
+            action: Action::Upload,
+            profile: None,
+            preset: None,
+            options: UploadPreset::default(),
+        }])
+    }
+
+    /// Looks up the profile called `name`
+    ///
+    /// Without a name the profile from `SHORTCLIP_PROFILE` or the default profile is used.
+    /// Config files with a top level `host` and `token` act as a single unnamed profile
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, String> {
+        let name = name
+            .map(|n| n.to_owned())
+            .or_else(|| std::env::var(PROFILE_VAR).ok())
+            .or_else(|| self.default_profile.clone());
+
+        if let Some(name) = name {
+            return self
+                .profiles
+                .get(&name)
+                .cloned()
+                .ok_or(format!("Unknown profile \"{name}\""));
+        }
+
+        match (&self.host, &self.token) {
+            (Some(host), Some(token)) => Ok(Profile {
+                host: host.clone(),
+                token: token.clone(),
+                defaults: UploadPreset::default(),
+            }),
+            _ if self.profiles.len() == 1 => Ok(self.profiles.values().next().unwrap().clone()),
+            _ => Err(
+                "No profile selected, set default_profile in the config or use --profile"
+                    .to_owned(),
+            ),
+        }
+    }
+
+    /// Combines the defaults, those of `profile` and of the action, the preset of `args`
+    /// and its overrides, each one replacing the options set by the ones before
+    pub fn upload_options(
+        &self,
+        profile: &Profile,
+        args: &UploadArgs,
+    ) -> Result<UploadOptions, String> {
+        let mut options = self
+            .defaults
+            .clone()
+            .with(&profile.defaults)
+            .with(&args.defaults);
+
+        if let Some(name) = &args.preset {
+            let preset = self
+                .presets
+                .get(name)
+                .ok_or(format!("Unknown preset \"{name}\""))?;
+            options = options.with(preset);
+        }
+
+        Ok(options.with(&args.overrides).into())
+    }
+}
+
+/// Options of an upload, unset fields fall back to the next less specific source
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UploadPreset {
+    /// Time until uploaded clips expire, in seconds or with a suffix like `"1h"`
+    #[serde(default, with = "duration", skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+    /// Visibility of uploaded clips, `public`, `unlisted` or `private`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    /// Number of downloads after which clips are deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_views: Option<u32>,
+    /// Encrypt clips before uploading, the key is only part of the link
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<bool>,
+}
+
+impl UploadPreset {
+    /// These options with every field that is set in `other` replaced
+    pub fn with(self, other: &UploadPreset) -> UploadPreset {
+        UploadPreset {
+            ttl: other.ttl.or(self.ttl),
+            visibility: other.visibility.or(self.visibility),
+            max_views: other.max_views.or(self.max_views),
+            encrypt: other.encrypt.or(self.encrypt),
+        }
+    }
+}
+
+impl From<UploadPreset> for UploadOptions {
+    fn from(preset: UploadPreset) -> Self {
+        UploadOptions {
+            ttl: preset.ttl,
+            visibility: preset.visibility,
+            max_views: preset.max_views,
+            password: None,
+            encrypted: preset.encrypt.unwrap_or(false),
+        }
+    }
+}
+
+/// Durations are written either as seconds or as strings like `"30m"`
+mod duration {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Duration {
+        Seconds(u64),
+        Text(String),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+        match Option::<Duration>::deserialize(d)? {
+            Some(Duration::Seconds(s)) => Ok(Some(s)),
+            Some(Duration::Text(t)) => shared::parse_duration(&t)
+                .map(Some)
+                .ok_or_else(|| serde::de::Error::custom(format!("Invalid duration \"{t}\""))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn serialize<S: Serializer>(ttl: &Option<u64>, s: S) -> Result<S::Ok, S::Error> {
+        match ttl {
+            Some(ttl) => s.serialize_u64(*ttl),
+            None => s.serialize_none(),
+        }
+    }
 }
 
 static CONFIG: OnceLock<Config> = OnceLock::new();
@@ -15,7 +224,7 @@ pub fn load_config() -> &'static Config {
         let config_path = config_path();
 
         let data = fs::read(&config_path)
-            .expect(format!("Error reading config at {}", config_path.display()).as_str());
+            .unwrap_or_else(|_| panic!("Error reading config at {}", config_path.display()));
 
         serde_json::from_slice(&data).unwrap()
     })
@@ -74,3 +283,127 @@ fn config_path() -> PathBuf {
 
     config_folder_path.join("shortclip-config.json")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> Config {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn bindings() {
+        let default = config("{}").bindings().unwrap();
+        assert_eq!(default.len(), 1);
+        assert_eq!(default[0].keys, Hotkey::default());
+        assert_eq!(default[0].action, Action::Upload);
+
+        let bindings = config(
+            r#"{"hotkey": "Ctrl+X", "hotkeys": [
+                {"keys": "Ctrl+U"},
+                {"keys": "Ctrl+Shift+U", "action": "screenshot"}
+            ]}"#,
+        )
+        .bindings()
+        .unwrap();
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(bindings[1].action, Action::Screenshot);
+    }
+
+    #[test]
+    fn duplicate_bindings_are_rejected() {
+        let err = config(
+            r#"{"hotkeys": [
+                {"keys": "Ctrl+U"},
+                {"keys": "Ctrl+Shift+U", "action": "screenshot"},
+                {"keys": "control+u", "action": "shorten"}
+            ]}"#,
+        )
+        .bindings()
+        .unwrap_err();
+        assert_eq!(err, "Ctrl+U is bound more than once");
+    }
+
+    /// The only test reading `SHORTCLIP_PROFILE`, as it changes it
+    #[test]
+    fn profiles() {
+        std::env::remove_var(PROFILE_VAR);
+
+        // Config files from before profiles existed
+        let legacy = config(r#"{"host": "https://old", "token": "t"}"#);
+        assert_eq!(legacy.profile(None).unwrap().host, "https://old");
+        assert_eq!(
+            legacy.profile(Some("team")).unwrap_err(),
+            "Unknown profile \"team\""
+        );
+
+        let profiles = r#""profiles": {
+            "team": {"host": "https://team", "token": "a"},
+            "home": {"host": "https://home", "token": "b", "ttl": "1d"}
+        }"#;
+        let config_without_default = config(&format!("{{{profiles}}}"));
+        assert!(config_without_default.profile(None).is_err());
+        assert_eq!(
+            config_without_default.profile(Some("home")).unwrap().host,
+            "https://home"
+        );
+
+        let with_default = config(&format!(r#"{{{profiles}, "default_profile": "team"}}"#));
+        assert_eq!(with_default.profile(None).unwrap().host, "https://team");
+        std::env::set_var(PROFILE_VAR, "home");
+        assert_eq!(with_default.profile(None).unwrap().host, "https://home");
+        // The command line beats the environment
+        assert_eq!(
+            with_default.profile(Some("team")).unwrap().host,
+            "https://team"
+        );
+        std::env::remove_var(PROFILE_VAR);
+
+        // A single profile doesn't need to be selected
+        let single = config(r#"{"profiles": {"only": {"host": "h", "token": "t"}}}"#);
+        assert_eq!(single.profile(None).unwrap().host, "h");
+    }
+
+    #[test]
+    fn upload_options_are_layered() {
+        let config = config(
+            r#"{
+                "ttl": 60, "visibility": "unlisted", "max_views": 5,
+                "profiles": {"home": {"host": "h", "token": "t", "ttl": "1h", "visibility": "private"}},
+                "presets": {"burn": {"max_views": 1, "visibility": "public"}}
+            }"#,
+        );
+        let profile = config.profile(Some("home")).unwrap();
+
+        let options = config
+            .upload_options(&profile, &UploadArgs::default())
+            .unwrap();
+        assert_eq!(options.ttl, Some(3600));
+        assert_eq!(options.visibility, Some(Visibility::Private));
+        assert_eq!(options.max_views, Some(5));
+        assert!(!options.encrypted);
+
+        let mut args = UploadArgs {
+            preset: Some("burn".to_owned()),
+            overrides: UploadPreset {
+                max_views: Some(2),
+                encrypt: Some(true),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let options = config.upload_options(&profile, &args).unwrap();
+        assert_eq!(options.ttl, Some(3600));
+        assert_eq!(options.visibility, Some(Visibility::Public));
+        assert_eq!(options.max_views, Some(2));
+        assert!(options.encrypted);
+
+        args.preset = Some("missing".to_owned());
+        assert_eq!(
+            config.upload_options(&profile, &args).unwrap_err(),
+            "Unknown preset \"missing\""
+        );
+        assert!(serde_json::from_str::<Config>(r#"{"ttl": "soon"}"#).is_err());
+    }
+}
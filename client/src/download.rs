@@ -0,0 +1,16 @@
+use crate::{config::load_config, upload::ClipboardContent};
+
+/// Downloads a clip, decrypting it if the link contains a key
+///
+/// The token of the profile is only sent if the link points to its server
+pub fn download_clip(
+    link: &str,
+    profile: Option<&str>,
+) -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+    let clip = load_config().profile(profile)?.client()?.download(link)?;
+
+    Ok(ClipboardContent {
+        content_type: clip.content_type,
+        data: clip.data,
+    })
+}
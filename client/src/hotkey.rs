@@ -0,0 +1,271 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A key combination like `Ctrl+Shift+U`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hotkey {
+    pub modifiers: Modifiers,
+    pub key: Key,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    /// The Windows or Command key
+    pub super_key: bool,
+}
+
+/// Keys independent of the keyboard layout, platforms map them to their own key codes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// A lowercase ascii letter or a digit
+    Char(char),
+    /// `F1` to `F24`
+    Function(u8),
+    Space,
+    Enter,
+    Tab,
+    Escape,
+    Backspace,
+    Delete,
+    Insert,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Left,
+    Right,
+    Up,
+    Down,
+    PrintScreen,
+    Pause,
+}
+
+/// Names of the keys besides letters, digits and function keys, the first one is used for display
+const NAMED_KEYS: &[(Key, &[&str])] = &[
+    (Key::Space, &["Space"]),
+    (Key::Enter, &["Enter", "Return"]),
+    (Key::Tab, &["Tab"]),
+    (Key::Escape, &["Escape", "Esc"]),
+    (Key::Backspace, &["Backspace"]),
+    (Key::Delete, &["Delete", "Del"]),
+    (Key::Insert, &["Insert", "Ins"]),
+    (Key::Home, &["Home"]),
+    (Key::End, &["End"]),
+    (Key::PageUp, &["PageUp", "Prior"]),
+    (Key::PageDown, &["PageDown", "Next"]),
+    (Key::Left, &["Left"]),
+    (Key::Right, &["Right"]),
+    (Key::Up, &["Up"]),
+    (Key::Down, &["Down"]),
+    (Key::PrintScreen, &["Print", "PrintScreen", "PrtSc"]),
+    (Key::Pause, &["Pause"]),
+];
+
+impl Default for Hotkey {
+    /// `Ctrl+U`, the hotkey used before it was configurable
+    fn default() -> Self {
+        Hotkey {
+            modifiers: Modifiers {
+                ctrl: true,
+                ..Default::default()
+            },
+            key: Key::Char('u'),
+        }
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            if c.is_ascii_alphanumeric() {
+                return Ok(Key::Char(c.to_ascii_lowercase()));
+            }
+        }
+
+        if let Some(n) = s
+            .strip_prefix(['F', 'f'])
+            .and_then(|n| n.parse::<u8>().ok())
+        {
+            if (1..=24).contains(&n) {
+                return Ok(Key::Function(n));
+            }
+        }
+
+        NAMED_KEYS
+            .iter()
+            .find(|(_, names)| names.iter().any(|name| name.eq_ignore_ascii_case(s)))
+            .map(|(key, _)| *key)
+            .ok_or(format!("Unknown key \"{s}\""))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Char(c) => write!(f, "{}", c.to_ascii_uppercase()),
+            Key::Function(n) => write!(f, "F{n}"),
+            key => {
+                let (_, names) = NAMED_KEYS.iter().find(|(k, _)| k == key).unwrap();
+                f.write_str(names[0])
+            }
+        }
+    }
+}
+
+impl FromStr for Hotkey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = Modifiers::default();
+        let mut key = None;
+
+        for part in s.split('+').map(|p| p.trim()) {
+            let modifier = match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => &mut modifiers.ctrl,
+                "shift" => &mut modifiers.shift,
+                "alt" => &mut modifiers.alt,
+                "super" | "win" | "meta" | "cmd" => &mut modifiers.super_key,
+                _ => {
+                    if key.is_some() {
+                        return Err(format!("Hotkey \"{s}\" contains more than one key"));
+                    }
+                    key = Some(part.parse().map_err(|e| format!("{e} in hotkey \"{s}\""))?);
+                    continue;
+                }
+            };
+            *modifier = true;
+        }
+
+        Ok(Hotkey {
+            modifiers,
+            key: key.ok_or(format!("Hotkey \"{s}\" contains no key"))?,
+        })
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let m = &self.modifiers;
+        for (set, name) in [
+            (m.ctrl, "Ctrl"),
+            (m.shift, "Shift"),
+            (m.alt, "Alt"),
+            (m.super_key, "Super"),
+        ] {
+            if set {
+                write!(f, "{name}+")?;
+            }
+        }
+
+        write!(f, "{}", self.key)
+    }
+}
+
+impl Serialize for Hotkey {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hotkey {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hotkey(ctrl: bool, shift: bool, alt: bool, super_key: bool, key: Key) -> Hotkey {
+        Hotkey {
+            modifiers: Modifiers {
+                ctrl,
+                shift,
+                alt,
+                super_key,
+            },
+            key,
+        }
+    }
+
+    #[test]
+    fn parse_hotkeys() {
+        let cases = [
+            ("Ctrl+U", hotkey(true, false, false, false, Key::Char('u'))),
+            ("u", hotkey(false, false, false, false, Key::Char('u'))),
+            (
+                "control + shift + 1",
+                hotkey(true, true, false, false, Key::Char('1')),
+            ),
+            (
+                "Alt+F1",
+                hotkey(false, false, true, false, Key::Function(1)),
+            ),
+            (
+                "win+f24",
+                hotkey(false, false, false, true, Key::Function(24)),
+            ),
+            (
+                "Meta+Cmd+Super+Esc",
+                hotkey(false, false, false, true, Key::Escape),
+            ),
+            ("Ctrl+Return", hotkey(true, false, false, false, Key::Enter)),
+            (
+                "shift+prior",
+                hotkey(false, true, false, false, Key::PageUp),
+            ),
+            (
+                "Ctrl+Alt+PRTSC",
+                hotkey(true, false, true, false, Key::PrintScreen),
+            ),
+            ("Ctrl+Space", hotkey(true, false, false, false, Key::Space)),
+        ];
+
+        for (s, expected) in cases {
+            assert_eq!(s.parse::<Hotkey>(), Ok(expected), "{s}");
+        }
+    }
+
+    #[test]
+    fn reject_invalid_hotkeys() {
+        let cases = [
+            ("", "Unknown key \"\""),
+            ("Ctrl+Shift", "contains no key"),
+            ("Ctrl+A+B", "more than one key"),
+            ("Ctrl+Foo", "Unknown key \"Foo\""),
+            ("Ctrl+F0", "Unknown key \"F0\""),
+            ("Ctrl+F25", "Unknown key \"F25\""),
+            ("Ctrl+ä", "Unknown key \"ä\""),
+            ("Ctrl++", "Unknown key \"\""),
+        ];
+
+        for (s, expected) in cases {
+            let err = s.parse::<Hotkey>().unwrap_err();
+            assert!(err.contains(expected), "{s}: {err}");
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in [
+            "Ctrl+U",
+            "Ctrl+Shift+Alt+Super+F12",
+            "Alt+PageDown",
+            "Print",
+        ] {
+            let hotkey: Hotkey = s.parse().unwrap();
+            assert_eq!(hotkey.to_string(), s);
+        }
+    }
+}
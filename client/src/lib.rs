@@ -1,3 +1,8 @@
+pub mod actions;
+pub mod cli;
 pub mod config;
+pub mod download;
+pub mod hotkey;
+pub mod notify;
 pub mod sys;
 pub mod upload;
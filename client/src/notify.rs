@@ -0,0 +1,67 @@
+//! Desktop notifications about uploads, for when nobody watches the terminal
+
+use std::fmt::Display;
+
+use image::RgbaImage;
+
+use crate::config::load_config;
+use crate::sys::notification::show_notification;
+use crate::upload::ClipboardContent;
+
+/// Longest side of the thumbnail shown for uploaded images
+const THUMBNAIL_SIZE: u32 = 128;
+
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    pub image: Option<RgbaImage>,
+}
+
+/// Shows the link of an upload, with a thumbnail if an image was uploaded
+pub fn notify_uploaded(link: &str, content: &ClipboardContent) {
+    notify(uploaded(link, content));
+}
+
+/// The key of encrypted clips is left out, notification servers may keep a history on disk
+pub fn uploaded(link: &str, content: &ClipboardContent) -> Notification {
+    let (link, key) = match link.split_once('#') {
+        Some((link, _)) => (link, " (the key is only in the clipboard)"),
+        None => (link, ""),
+    };
+
+    Notification {
+        summary: "Link copied to the clipboard".to_owned(),
+        body: format!("{link}{key}"),
+        image: thumbnail(content),
+    }
+}
+
+/// Shows why an upload failed
+pub fn notify_failed(error: impl Display) {
+    notify(Notification {
+        summary: "Upload failed".to_owned(),
+        body: error.to_string(),
+        image: None,
+    });
+}
+
+fn notify(notification: Notification) {
+    if !load_config().notifications {
+        return;
+    }
+
+    // The upload itself worked, so this is only worth a message
+    if let Err(e) = show_notification(&notification) {
+        eprintln!("Unable to show notification: {e}");
+    }
+}
+
+fn thumbnail(content: &ClipboardContent) -> Option<RgbaImage> {
+    if !content.content_type.starts_with("image/") {
+        return None;
+    }
+
+    let image = image::load_from_memory(&content.data).ok()?;
+
+    Some(image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgba8())
+}
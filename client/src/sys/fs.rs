@@ -1,4 +1,8 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use shared::api::URI_LIST;
+
+use crate::upload::ClipboardContent;
 
 pub fn guess_path_content(path: &Path) -> String {
     if let Some(ext) = path.extension() {
@@ -13,6 +17,32 @@ pub fn guess_path_content(path: &Path) -> String {
     }
 }
 
+/// Turns copied text into content, uploading the file instead if it is a `file://` path
+pub fn text_content(text: String) -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+    if let Some(path) = text.strip_prefix("file://") {
+        let path = PathBuf::from(path);
+        path.try_exists()?;
+
+        let content_type = guess_path_content(&path);
+        let data = std::fs::read(path)?;
+
+        return Ok(ClipboardContent { content_type, data });
+    }
+
+    // Check if the content is a valid url
+    let content_type = if url::Url::parse(&text).is_ok() {
+        // Let the backend know that this is a url
+        URI_LIST.to_owned()
+    } else {
+        "text/plain".to_owned()
+    };
+
+    Ok(ClipboardContent {
+        content_type,
+        data: text.into_bytes(),
+    })
+}
+
 // Copied from https://github.com/zip-rs/zip/blob/3e88fe66c941d411cff5cf49778ba08c2ed93801/examples/write_dir.rs#L65
 /*
 use std::{
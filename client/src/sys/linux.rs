@@ -0,0 +1,263 @@
+//! Picks the X11 or Wayland backend at runtime, as the same binary runs on both
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, OnceLock};
+use std::time::Duration;
+use std::{env, panic, thread};
+
+use super::{wayland, x11};
+
+/// The display server the client talks to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Session {
+    X11,
+    Wayland,
+}
+
+static SESSION: OnceLock<Session> = OnceLock::new();
+
+/// Wayland if a compositor is running, unless `XDG_SESSION_TYPE` asks for X11
+///
+/// Setting `XDG_SESSION_TYPE=x11` falls back to XWayland on compositors without data-control
+pub fn session() -> Session {
+    *SESSION.get_or_init(|| {
+        let session_type = env::var("XDG_SESSION_TYPE").unwrap_or_default();
+
+        if env::var_os("WAYLAND_DISPLAY").is_none() || session_type == "x11" {
+            Session::X11
+        } else {
+            Session::Wayland
+        }
+    })
+}
+
+pub mod clipboard {
+    use super::{session, wayland, x11, Session};
+    use crate::upload::ClipboardContent;
+
+    /// Copies the text, also to the primary selection if `primary` is set
+    pub fn set_clipboard(string: &str, primary: bool) -> Result<(), Box<dyn std::error::Error>> {
+        match session() {
+            Session::X11 => x11::clipboard::set_clipboard(string, primary),
+            Session::Wayland => wayland::clipboard::set_clipboard(string, primary),
+        }
+    }
+
+    pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+        match session() {
+            Session::X11 => x11::clipboard::read_clipboard(),
+            Session::Wayland => wayland::clipboard::read_clipboard(),
+        }
+    }
+
+    /// Reads the primary selection, the text that was last selected
+    pub fn read_selection() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+        match session() {
+            Session::X11 => x11::clipboard::read_selection(),
+            Session::Wayland => wayland::clipboard::read_selection(),
+        }
+    }
+}
+
+pub mod hotkey {
+    use super::{session, wayland, x11, Session};
+    use crate::hotkey::Hotkey;
+
+    /// Listens for every hotkey and calls `callback` with the index of the one that was pressed
+    pub fn create_listener<F>(
+        hotkeys: &[Hotkey],
+        callback: F,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: Fn(usize),
+    {
+        match session() {
+            Session::X11 => x11::hotkey::create_listener(hotkeys, callback),
+            Session::Wayland => wayland::hotkey::create_listener(hotkeys, callback),
+        }
+    }
+}
+
+pub mod screenshot {
+    use super::{session, wayland, x11, Session};
+    use crate::upload::ClipboardContent;
+
+    pub fn take_screenshot() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+        match session() {
+            Session::X11 => x11::screenshot::take_screenshot(),
+            Session::Wayland => wayland::screenshot::take_screenshot(),
+        }
+    }
+}
+
+/// Hidden first argument that starts the binaries as the background process of
+/// `serve_in_background`
+const SERVE_SELECTION: &str = "serve-selection";
+
+/// How long the background process may take to report that it serves the clipboard
+const SERVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Offers `text` on the clipboard from a background process, so it outlives this one
+///
+/// X11 selections only live as long as their owner and Wayland clipboards as long as their
+/// source, so a process has to keep serving them after the client exits. The binary is
+/// started again with the hidden `serve-selection` argument and reads the text from stdin.
+/// Unlike a fork, the new process doesn't inherit the threads of the daemon or its connections.
+/// The error the background process reports once it is set up is returned here, a process
+/// that doesn't report within `SERVE_TIMEOUT` is killed
+pub fn serve_in_background(text: &str, primary: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .arg(SERVE_SELECTION)
+        .args(primary.then_some("primary"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+
+    // Its own session keeps the process running when the terminal of the client is closed
+    // SAFETY: setsid is async-signal-safe
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+
+    let mut child = command.spawn()?;
+    if let Err(e) = child.stdin.take().unwrap().write_all(text.as_bytes()) {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(e.into());
+    }
+
+    wait_for_status(child, SERVE_TIMEOUT)
+}
+
+/// Waits until `child` reports its status on stdout, it is killed after `timeout`
+fn wait_for_status(mut child: Child, timeout: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    // The process keeps stdout open while serving, it only reports a single line
+    let stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut status = String::new();
+        let result = BufReader::new(stdout).read_line(&mut status);
+        let _ = sender.send(result.map(|_| status));
+    });
+
+    let Ok(status) = receiver.recv_timeout(timeout) else {
+        // A process stuck in its setup would never let go of the clipboard
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err("The background process didn't start serving the clipboard in time".into());
+    };
+
+    // The daemon keeps running, so the process has to be reaped once it stops serving
+    thread::spawn(move || child.wait());
+
+    parse_status(&status?)
+}
+
+/// Serves the clipboard if this process was started by `serve_in_background`
+///
+/// Has to be called first thing in `main`, the process exits once another application
+/// owns the clipboard
+pub fn serve_if_requested() {
+    let mut args = env::args().skip(1);
+    if args.next().as_deref() != Some(SERVE_SELECTION) {
+        return;
+    }
+    let primary = args.next().as_deref() == Some("primary");
+
+    // Whatever happens, the process must not continue into `main`. A panic before
+    // reporting closes stdout, which the client sees as an unexpected exit
+    let served = panic::catch_unwind(|| serve_selection(primary));
+
+    // SAFETY: exiting directly is fine, stdout was flushed when reporting
+    unsafe { libc::_exit(if served.is_ok() { 0 } else { 1 }) }
+}
+
+fn serve_selection(primary: bool) {
+    let ready = Ready(io::stdout());
+    let mut text = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut text) {
+        return ready.report(Err(e.into()));
+    }
+
+    match session() {
+        Session::X11 => x11::clipboard::serve_clipboard(&text, primary, ready),
+        Session::Wayland => wayland::clipboard::serve_clipboard(&text, primary, ready),
+    }
+}
+
+fn parse_status(status: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match status.trim_end().split_once(':') {
+        Some(("ok", _)) => Ok(()),
+        Some((_, error)) => Err(error.into()),
+        None => Err("The background process exited unexpectedly".into()),
+    }
+}
+
+/// Reports the outcome of the setup of the background process to the client
+pub struct Ready(io::Stdout);
+
+impl Ready {
+    pub fn report(mut self, result: Result<(), Box<dyn std::error::Error>>) {
+        // A single line, so the error can't end the status early
+        let status = match result {
+            Ok(()) => "ok:\n".to_owned(),
+            Err(e) => format!("error:{}\n", e.to_string().replace('\n', " ")),
+        };
+        let _ = self.0.write_all(status.as_bytes());
+        let _ = self.0.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn spawn(script: &str) -> Child {
+        Command::new("sh")
+            .args(["-c", script])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap()
+    }
+
+    #[test]
+    fn status_is_reported() {
+        let timeout = Duration::from_secs(10);
+
+        assert!(wait_for_status(spawn("echo ok:; sleep 1"), timeout).is_ok());
+        assert_eq!(
+            wait_for_status(spawn("echo error:No display"), timeout)
+                .unwrap_err()
+                .to_string(),
+            "No display"
+        );
+        assert_eq!(
+            wait_for_status(spawn("exit 1"), timeout)
+                .unwrap_err()
+                .to_string(),
+            "The background process exited unexpectedly"
+        );
+    }
+
+    #[test]
+    fn stuck_processes_are_killed() {
+        let child = spawn("exec sleep 30");
+        let pid = child.id() as libc::pid_t;
+        let start = Instant::now();
+
+        let err = wait_for_status(child, Duration::from_millis(200)).unwrap_err();
+        assert!(err.to_string().contains("in time"));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        // The process was killed and reaped, so its pid is gone
+        // SAFETY: signal 0 only checks whether the process exists
+        assert_eq!(unsafe { libc::kill(pid, 0) }, -1);
+    }
+}
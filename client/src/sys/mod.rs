@@ -4,8 +4,15 @@ mod windows;
 pub use windows::*;
 
 #[cfg(target_os = "linux")]
-pub mod x11;
+pub mod linux;
+#[cfg(target_os = "linux")]
+pub use linux::*;
+#[cfg(target_os = "linux")]
+pub mod wayland;
 #[cfg(target_os = "linux")]
-pub use x11::*;
+pub mod x11;
 
 pub mod fs;
+/// Notifications go through D-Bus, whichever display server is running
+#[cfg(target_os = "linux")]
+pub mod notification;
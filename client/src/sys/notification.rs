@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use zbus::zvariant::Value;
+
+use crate::notify::Notification;
+
+#[zbus::proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Shows a notification through the `org.freedesktop.Notifications` service of the session bus
+pub fn show_notification(notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+    send_notification(&zbus::blocking::Connection::session()?, notification)
+}
+
+fn send_notification(
+    conn: &zbus::blocking::Connection,
+    notification: &Notification,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let proxy = NotificationsProxyBlocking::new(conn)?;
+
+    let mut hints = HashMap::new();
+    if let Some(image) = &notification.image {
+        // Raw pixels as (width, height, rowstride, has_alpha, bits_per_sample, channels, data)
+        let (width, height) = image.dimensions();
+        let data = (
+            width as i32,
+            height as i32,
+            width as i32 * 4,
+            true,
+            8,
+            4,
+            image.as_raw().clone(),
+        );
+        hints.insert("image-data", Value::from(data));
+    }
+
+    proxy.notify(
+        "short_clip",
+        0,
+        "",
+        &notification.summary,
+        &escape_markup(&notification.body),
+        &[],
+        hints,
+        // The server decides how long the notification is shown
+        -1,
+    )?;
+
+    Ok(())
+}
+
+/// Servers may interpret the body as markup, which would break links containing `&`
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Cursor};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::{Arc, Mutex};
+
+    use image::RgbaImage;
+    use zbus::zvariant::OwnedValue;
+
+    use super::*;
+    use crate::notify::uploaded;
+    use crate::upload::ClipboardContent;
+
+    type ImageData = (i32, i32, i32, bool, i32, i32, Vec<u8>);
+
+    #[derive(Default)]
+    struct Received {
+        summary: String,
+        body: String,
+        image: Option<ImageData>,
+    }
+
+    /// Records the notifications it receives
+    struct MockServer(Arc<Mutex<Vec<Received>>>);
+
+    #[zbus::interface(name = "org.freedesktop.Notifications")]
+    impl MockServer {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &self,
+            _app_name: &str,
+            _replaces_id: u32,
+            _app_icon: &str,
+            summary: &str,
+            body: &str,
+            _actions: Vec<String>,
+            hints: HashMap<String, OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            let image = hints
+                .get("image-data")
+                .map(|v| ImageData::try_from(v.try_clone().unwrap()).unwrap());
+
+            let mut received = self.0.lock().unwrap();
+            received.push(Received {
+                summary: summary.to_owned(),
+                body: body.to_owned(),
+                image,
+            });
+            received.len() as u32
+        }
+    }
+
+    /// A private bus, stopped when dropped
+    struct Bus(Child);
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    /// Starts a private bus, `None` if `dbus-daemon` isn't installed
+    fn start_bus() -> Option<(Bus, String)> {
+        let mut child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+
+        let mut address = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        Some((Bus(child), address.trim().to_owned()))
+    }
+
+    #[test]
+    fn notifications_reach_the_server() {
+        let Some((_bus, address)) = start_bus() else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let _server = zbus::blocking::connection::Builder::address(address.as_str())
+            .unwrap()
+            .name("org.freedesktop.Notifications")
+            .unwrap()
+            .serve_at(
+                "/org/freedesktop/Notifications",
+                MockServer(received.clone()),
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        let conn = zbus::blocking::connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let mut png = Vec::new();
+        RgbaImage::new(300, 200)
+            .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+        let image = ClipboardContent {
+            content_type: "image/png".to_owned(),
+            data: png,
+        };
+        let text = ClipboardContent {
+            content_type: "text/plain".to_owned(),
+            data: b"hello".to_vec(),
+        };
+
+        send_notification(&conn, &uploaded("https://clip.example/abc?a&b", &image)).unwrap();
+        send_notification(
+            &conn,
+            &uploaded("https://clip.example/def#secretkey", &text),
+        )
+        .unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+
+        assert_eq!(received[0].summary, "Link copied to the clipboard");
+        assert_eq!(received[0].body, "https://clip.example/abc?a&amp;b");
+        let (width, height, rowstride, has_alpha, bits, channels, data) =
+            received[0].image.clone().unwrap();
+        assert_eq!((width, height, rowstride), (128, 85, 128 * 4));
+        assert!(has_alpha);
+        assert_eq!((bits, channels), (8, 4));
+        assert_eq!(data.len(), 128 * 85 * 4);
+
+        assert_eq!(
+            received[1].body,
+            "https://clip.example/def (the key is only in the clipboard)"
+        );
+        assert!(!received[1].body.contains("secretkey"));
+        assert!(received[1].image.is_none());
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::Read;
+
+use wl_clipboard_rs::copy::{self, Source};
+use wl_clipboard_rs::paste::{self, get_contents, get_mime_types_ordered, ClipboardType, Seat};
+
+use crate::{
+    sys::{
+        fs::text_content,
+        linux::{serve_in_background, Ready},
+    },
+    upload::ClipboardContent,
+};
+
+/// Copies the text, it is offered by a background process until another application takes over
+///
+/// Errors like a missing protocol are still reported to us by the background process
+pub fn set_clipboard(string: &str, primary: bool) -> Result<(), Box<dyn std::error::Error>> {
+    serve_in_background(string, primary)
+}
+
+/// Offers the text through the data-control protocol until another application takes over
+///
+/// Runs in the background process started by `set_clipboard`
+pub fn serve_clipboard(text: &str, primary: bool, ready: Ready) {
+    let mut options = copy::Options::new();
+    options.foreground(true);
+    if primary {
+        options.clipboard(copy::ClipboardType::Both);
+    }
+
+    match options.prepare_copy(Source::Bytes(text.as_bytes().into()), copy::MimeType::Text) {
+        Ok(prepared) => {
+            ready.report(Ok(()));
+            let _ = prepared.serve();
+        }
+        Err(e) => ready.report(Err(e.into())),
+    }
+}
+
+pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+    let mime_types = get_mime_types_ordered(ClipboardType::Regular, Seat::Unspecified)
+        .map_err(|e| clipboard_error(e, "Clipboard empty"))?;
+
+    // Images are uploaded as they are, in whatever format they were copied
+    if let Some(image_type) = mime_types.iter().find(|t| t.starts_with("image/")) {
+        let (data, content_type) = read(
+            ClipboardType::Regular,
+            paste::MimeType::Specific(image_type),
+            "Clipboard empty",
+        )?;
+
+        return Ok(ClipboardContent { content_type, data });
+    }
+
+    let (data, _) = read(
+        ClipboardType::Regular,
+        paste::MimeType::Text,
+        "Clipboard empty",
+    )?;
+
+    text_content(String::from_utf8(data)?)
+}
+
+/// Reads the primary selection, the text that was last selected
+pub fn read_selection() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+    let (data, _) = read(
+        ClipboardType::Primary,
+        paste::MimeType::Text,
+        "Nothing is selected",
+    )?;
+
+    text_content(String::from_utf8(data)?)
+}
+
+/// Reads the clipboard in the given type, returning the data and its actual type
+fn read(
+    clipboard: ClipboardType,
+    mime_type: paste::MimeType,
+    empty: &'static str,
+) -> Result<(Vec<u8>, String), Box<dyn std::error::Error>> {
+    let (mut pipe, mime_type) = get_contents(clipboard, Seat::Unspecified, mime_type)
+        .map_err(|e| clipboard_error(e, empty))?;
+
+    let mut data = Vec::new();
+    pipe.read_to_end(&mut data)?;
+
+    Ok((data, mime_type))
+}
+
+/// Errors that only mean there is nothing to read are replaced with `empty`
+fn clipboard_error(e: paste::Error, empty: &'static str) -> Box<dyn std::error::Error> {
+    match e {
+        paste::Error::NoSeats | paste::Error::ClipboardEmpty | paste::Error::NoMimeType => {
+            empty.into()
+        }
+        e => e.into(),
+    }
+}
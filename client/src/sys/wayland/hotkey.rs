@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use zbus::blocking::Connection;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+
+use crate::hotkey::{Hotkey, Key};
+
+#[zbus::proxy(
+    interface = "org.freedesktop.portal.GlobalShortcuts",
+    default_service = "org.freedesktop.portal.Desktop",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+trait GlobalShortcuts {
+    fn create_session(&self, options: HashMap<&str, Value<'_>>) -> zbus::Result<OwnedObjectPath>;
+
+    fn bind_shortcuts(
+        &self,
+        session_handle: &ObjectPath<'_>,
+        shortcuts: &[(&str, HashMap<&str, Value<'_>>)],
+        parent_window: &str,
+        options: HashMap<&str, Value<'_>>,
+    ) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(signal)]
+    fn activated(
+        &self,
+        session_handle: ObjectPath<'_>,
+        shortcut_id: &str,
+        timestamp: u64,
+        options: HashMap<&str, Value<'_>>,
+    ) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.portal.Request",
+    default_service = "org.freedesktop.portal.Desktop"
+)]
+trait Request {
+    #[zbus(signal)]
+    fn response(&self, response: u32, results: HashMap<String, OwnedValue>) -> zbus::Result<()>;
+}
+
+/// Registers the hotkeys with the XDG GlobalShortcuts portal and calls `callback` with the index
+/// of the one that was pressed
+///
+/// The hotkeys are only suggestions, the desktop lets the user confirm or change them
+pub fn create_listener<F>(hotkeys: &[Hotkey], callback: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Fn(usize),
+{
+    let conn = Connection::session()?;
+    let portal = GlobalShortcutsProxyBlocking::new(&conn)?;
+
+    let results = request(&conn, "short_clip_session", |token| {
+        portal.create_session(HashMap::from([
+            ("handle_token", Value::from(token)),
+            ("session_handle_token", Value::from("short_clip")),
+        ]))
+    })?;
+    let session = session_handle(&results)?;
+
+    // Listen before binding, so no activation can be missed
+    let activations = portal.receive_activated()?;
+
+    let ids: Vec<String> = (0..hotkeys.len()).map(|i| i.to_string()).collect();
+    let shortcuts: Vec<_> = hotkeys
+        .iter()
+        .zip(&ids)
+        .map(|(hotkey, id)| {
+            let options = HashMap::from([
+                ("description", Value::from(format!("short_clip {hotkey}"))),
+                ("preferred_trigger", Value::from(trigger(hotkey))),
+            ]);
+            (id.as_str(), options)
+        })
+        .collect();
+
+    request(&conn, "short_clip_bind", |token| {
+        portal.bind_shortcuts(
+            &session.as_ref(),
+            &shortcuts,
+            "",
+            HashMap::from([("handle_token", Value::from(token))]),
+        )
+    })?;
+
+    for activation in activations {
+        let args = activation.args()?;
+        if args.session_handle() != &session.as_ref() {
+            continue;
+        }
+
+        if let Ok(index) = args.shortcut_id().parse::<usize>() {
+            if index < hotkeys.len() {
+                callback(index);
+            }
+        }
+    }
+
+    Err("The GlobalShortcuts portal closed the connection".into())
+}
+
+/// Calls a portal method and waits for the `Response` signal of the request object it creates
+fn request<F>(
+    conn: &Connection,
+    token: &str,
+    call: F,
+) -> Result<HashMap<String, OwnedValue>, Box<dyn std::error::Error>>
+where
+    F: FnOnce(&str) -> zbus::Result<OwnedObjectPath>,
+{
+    // The path of the request is known in advance, so we can subscribe before calling the method
+    let sender = conn
+        .unique_name()
+        .ok_or("Not connected to the session bus")?
+        .trim_start_matches(':')
+        .replace('.', "_");
+    let path = format!("/org/freedesktop/portal/desktop/request/{sender}/{token}");
+
+    let request = RequestProxyBlocking::builder(conn).path(path)?.build()?;
+    let mut responses = request.receive_response()?;
+
+    call(token)?;
+
+    let response = responses
+        .next()
+        .ok_or("The GlobalShortcuts portal didn't answer")?;
+    let args = response.args()?;
+
+    match args.response() {
+        0 => Ok(args.results().clone()),
+        1 => Err("Registering the hotkeys was cancelled".into()),
+        _ => Err("The GlobalShortcuts portal failed to register the hotkeys".into()),
+    }
+}
+
+/// The handle of a created session, older portals return it as string
+fn session_handle(
+    results: &HashMap<String, OwnedValue>,
+) -> Result<OwnedObjectPath, Box<dyn std::error::Error>> {
+    let value = results
+        .get("session_handle")
+        .ok_or("The GlobalShortcuts portal didn't create a session")?;
+
+    match &**value {
+        Value::ObjectPath(path) => Ok(path.clone().into()),
+        Value::Str(path) => Ok(ObjectPath::try_from(path.as_str())?.into()),
+        _ => Err("The GlobalShortcuts portal returned an invalid session".into()),
+    }
+}
+
+/// The hotkey as trigger of the XDG shortcuts specification, like `CTRL+SHIFT+u`
+fn trigger(hotkey: &Hotkey) -> String {
+    let m = &hotkey.modifiers;
+    let mut result = String::new();
+
+    for (set, name) in [
+        (m.ctrl, "CTRL"),
+        (m.shift, "SHIFT"),
+        (m.alt, "ALT"),
+        (m.super_key, "LOGO"),
+    ] {
+        if set {
+            result.push_str(name);
+            result.push('+');
+        }
+    }
+
+    result.push_str(&keysym_name(hotkey.key));
+    result
+}
+
+/// The XKB keysym name of a key
+fn keysym_name(key: Key) -> String {
+    let name = match key {
+        Key::Char(c) => return c.to_string(),
+        Key::Function(n) => return format!("F{n}"),
+        Key::Space => "space",
+        Key::Enter => "Return",
+        Key::Tab => "Tab",
+        Key::Escape => "Escape",
+        Key::Backspace => "BackSpace",
+        Key::Delete => "Delete",
+        Key::Insert => "Insert",
+        Key::Home => "Home",
+        Key::End => "End",
+        Key::PageUp => "Page_Up",
+        Key::PageDown => "Page_Down",
+        Key::Left => "Left",
+        Key::Right => "Right",
+        Key::Up => "Up",
+        Key::Down => "Down",
+        Key::PrintScreen => "Print",
+        Key::Pause => "Pause",
+    };
+
+    name.to_owned()
+}
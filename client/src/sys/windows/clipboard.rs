@@ -4,17 +4,16 @@ use clipboard_win::formats;
 use clipboard_win::get_clipboard;
 use image::codecs::png::PngEncoder;
 use image::ImageFormat;
+use shared::api::URI_LIST;
 
 use crate::sys::fs::guess_path_content;
 use crate::upload::ClipboardContent;
 
-pub fn set_clipboard(string: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Windows has no primary selection, so `_primary` is ignored
+pub fn set_clipboard(string: &str, _primary: bool) -> Result<(), Box<dyn std::error::Error>> {
     match clipboard_win::set_clipboard_string(string) {
         Ok(_) => Ok(()),
-        Err(e) => Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            e.to_string(),
-        ))),
+        Err(e) => Err(Box::new(std::io::Error::other(e.to_string()))),
     }
 }
 
@@ -37,7 +36,7 @@ pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>>
         // Filter valid paths
         let paths: Vec<PathBuf> = file_list
             .into_iter()
-            .map(|f| PathBuf::from(f))
+            .map(PathBuf::from)
             .filter(|p| p.try_exists().unwrap_or(false))
             .collect();
 
@@ -47,11 +46,8 @@ pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>>
             let content_type = guess_path_content(path);
             let data = std::fs::read(path)?;
 
-            content = ClipboardContent {
-                content_type: content_type,
-                data: data,
-            }
-        } else if paths.len() >= 1 {
+            content = ClipboardContent { content_type, data }
+        } else if !paths.is_empty() {
             // TODO: Implement file zipping
             // For now multiple files will be ignored and only the first one gets uploaded
             let path = &paths[0];
@@ -59,10 +55,7 @@ pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>>
             let content_type = guess_path_content(path);
             let data = std::fs::read(path)?;
 
-            content = ClipboardContent {
-                content_type: content_type,
-                data: data,
-            }
+            content = ClipboardContent { content_type, data }
         } else {
             return Err(Box::new(std::io::Error::new(
                 std::io::ErrorKind::NotFound,
@@ -70,15 +63,12 @@ pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>>
             )));
         }
     } else if let Ok(text) = get_clipboard::<String, _>(formats::Unicode) {
-        let content_type: String;
-
-        // Check if the content is a valid url
-        if let Ok(_) = url::Url::parse(&clipboard_content) {
-            // Let the backend know that this is a url
-            content_type = "text/uri-list".to_owned();
+        // Let the backend know if this is a url
+        let content_type = if url::Url::parse(&text).is_ok() {
+            URI_LIST.to_owned()
         } else {
-            content_type = "text/plain".to_owned();
-        }
+            "text/plain".to_owned()
+        };
 
         content = ClipboardContent {
             content_type,
@@ -93,3 +83,8 @@ pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>>
 
     Ok(content)
 }
+
+/// Windows has no primary selection, only the clipboard
+pub fn read_selection() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+    Err("The primary selection only exists on X11".into())
+}
@@ -1,39 +1,86 @@
 use windows::Win32::{
     Foundation::HWND,
-    UI::Input::KeyboardAndMouse::{MOD_CONTROL, VK_U},
+    UI::Input::KeyboardAndMouse::{
+        RegisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, MOD_WIN,
+    },
+    UI::WindowsAndMessaging::{GetMessageW, MSG, WM_HOTKEY},
 };
 
-pub fn create_listener<F>(callback: F) -> Result<(), Box<dyn std::error::Error>>
+use crate::hotkey::{Hotkey, Key};
+
+/// Registers every hotkey and calls `callback` with the index of the one that was pressed
+pub fn create_listener<F>(hotkeys: &[Hotkey], callback: F) -> Result<(), Box<dyn std::error::Error>>
 where
-    F: Fn() -> Result<(), Box<dyn std::error::Error>>,
+    F: Fn(usize),
 {
     unsafe {
-        windows::Win32::UI::Input::KeyboardAndMouse::RegisterHotKey(
-            HWND::default(),
-            1,
-            MOD_CONTROL,
-            VK_U.0 as u32,
-        )?;
-
-        loop {
-            // This okay as the value is never read until its initialised
-            #[allow(invalid_value)]
-            let mut msg = std::mem::MaybeUninit::uninit().assume_init();
-            while windows::Win32::UI::WindowsAndMessaging::GetMessageW(
-                &mut msg,
+        for (i, hotkey) in hotkeys.iter().enumerate() {
+            // Ids start at 1, the index is recovered from them when a hotkey fires
+            RegisterHotKey(
                 HWND::default(),
-                0,
-                0,
+                i as i32 + 1,
+                modifiers(hotkey),
+                virtual_key(hotkey.key),
             )
-            .as_bool()
-            {
-                // Why are we doing this?
-                if msg.wParam.0 == 0 {
+            .map_err(|e| {
+                format!("{hotkey} is already used by another application, choose a different hotkey ({e})")
+            })?;
+        }
+
+        loop {
+            let mut msg = MSG::default();
+            while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {
+                if msg.message != WM_HOTKEY || msg.wParam.0 == 0 {
                     continue;
                 }
 
-                callback()?;
+                callback(msg.wParam.0 - 1);
             }
         }
     }
 }
+
+fn modifiers(hotkey: &Hotkey) -> HOT_KEY_MODIFIERS {
+    let m = &hotkey.modifiers;
+    // Holding the keys down doesn't trigger the hotkey repeatedly
+    let mut result = MOD_NOREPEAT;
+
+    for (set, modifier) in [
+        (m.ctrl, MOD_CONTROL),
+        (m.shift, MOD_SHIFT),
+        (m.alt, MOD_ALT),
+        (m.super_key, MOD_WIN),
+    ] {
+        if set {
+            result |= modifier;
+        }
+    }
+
+    result
+}
+
+/// The virtual key code of a key
+fn virtual_key(key: Key) -> u32 {
+    match key {
+        // Letters and digits use their uppercase ascii value
+        Key::Char(c) => c.to_ascii_uppercase() as u32,
+        Key::Function(n) => 0x6f + n as u32,
+        Key::Space => 0x20,
+        Key::Enter => 0x0d,
+        Key::Tab => 0x09,
+        Key::Escape => 0x1b,
+        Key::Backspace => 0x08,
+        Key::Delete => 0x2e,
+        Key::Insert => 0x2d,
+        Key::Home => 0x24,
+        Key::End => 0x23,
+        Key::PageUp => 0x21,
+        Key::PageDown => 0x22,
+        Key::Left => 0x25,
+        Key::Right => 0x27,
+        Key::Up => 0x26,
+        Key::Down => 0x28,
+        Key::PrintScreen => 0x2c,
+        Key::Pause => 0x13,
+    }
+}
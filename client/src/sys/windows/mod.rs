@@ -1,2 +1,7 @@
 pub mod clipboard;
 pub mod hotkey;
+pub mod notification;
+pub mod screenshot;
+
+/// Clipboards on Windows outlive the process that set them, there is nothing to serve
+pub fn serve_if_requested() {}
@@ -0,0 +1,5 @@
+use crate::notify::Notification;
+
+pub fn show_notification(_notification: &Notification) -> Result<(), Box<dyn std::error::Error>> {
+    Err("Notifications aren't supported on Windows yet".into())
+}
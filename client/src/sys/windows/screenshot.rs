@@ -0,0 +1,5 @@
+use crate::upload::ClipboardContent;
+
+pub fn take_screenshot() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+    Err("Screenshots aren't supported on Windows yet".into())
+}
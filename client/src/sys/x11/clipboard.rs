@@ -1,18 +1,193 @@
-use std::{path::PathBuf, time::Duration};
-
-use arboard::Clipboard;
+use arboard::{Clipboard, GetExtLinux, LinuxClipboardKind};
 use image::codecs::png::PngEncoder;
+use xcb::x;
+
+use crate::{
+    sys::{
+        fs::text_content,
+        linux::{serve_in_background, Ready},
+    },
+    upload::ClipboardContent,
+};
+
+/// Copies the text, it is offered by a background process until another application takes over
+pub fn set_clipboard(string: &str, primary: bool) -> Result<(), Box<dyn std::error::Error>> {
+    serve_in_background(string, primary)
+}
 
-use crate::{sys::fs::guess_path_content, upload::ClipboardContent};
+/// Owns the clipboard, and the primary selection if `primary` is set, until another
+/// application takes over
+///
+/// Runs in the background process started by `set_clipboard`
+pub fn serve_clipboard(text: &str, primary: bool, ready: Ready) {
+    match SelectionOwner::new(text, primary) {
+        Ok(owner) => {
+            ready.report(Ok(()));
+            let _ = owner.serve();
+        }
+        Err(e) => ready.report(Err(e)),
+    }
+}
 
-pub fn set_clipboard(string: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let mut clipboard = Clipboard::new()?;
-    clipboard.set_text(string)?;
+struct SelectionOwner<'a> {
+    conn: xcb::Connection,
+    text: &'a str,
+    /// Selections we still own
+    selections: Vec<x::Atom>,
+    atoms: Atoms,
+}
+
+struct Atoms {
+    targets: x::Atom,
+    /// Targets the text is offered as, the first one is the preferred
+    text: Vec<x::Atom>,
+    /// `TEXT`, which asks for the text in any type
+    text_any: x::Atom,
+}
 
-    // Otherwise clipboard contents will be dropped immediately
-    std::thread::sleep(Duration::from_millis(100));
+impl<'a> SelectionOwner<'a> {
+    fn new(text: &'a str, primary: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let (conn, screen_num) = xcb::Connection::connect(None)?;
+        let screen = conn.get_setup().roots().nth(screen_num as usize).unwrap();
+
+        // Selections are owned by a window, it is never mapped
+        let window = conn.generate_id();
+        conn.send_and_check_request(&x::CreateWindow {
+            depth: x::COPY_FROM_PARENT as u8,
+            wid: window,
+            parent: screen.root(),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            border_width: 0,
+            class: x::WindowClass::InputOnly,
+            visual: screen.root_visual(),
+            value_list: &[],
+        })?;
+
+        let text_any = intern_atom(&conn, "TEXT")?;
+        let atoms = Atoms {
+            targets: intern_atom(&conn, "TARGETS")?,
+            text: vec![
+                intern_atom(&conn, "UTF8_STRING")?,
+                intern_atom(&conn, "text/plain;charset=utf-8")?,
+                intern_atom(&conn, "text/plain")?,
+                text_any,
+                x::ATOM_STRING,
+            ],
+            text_any,
+        };
+
+        let mut selections = vec![intern_atom(&conn, "CLIPBOARD")?];
+        if primary {
+            selections.push(x::ATOM_PRIMARY);
+        }
 
-    Ok(())
+        for &selection in &selections {
+            conn.send_and_check_request(&x::SetSelectionOwner {
+                owner: window,
+                selection,
+                time: x::CURRENT_TIME,
+            })?;
+
+            let owner =
+                conn.wait_for_reply(conn.send_request(&x::GetSelectionOwner { selection }))?;
+            if owner.owner() != window {
+                return Err("Unable to take ownership of the clipboard".into());
+            }
+        }
+
+        Ok(Self {
+            conn,
+            text,
+            selections,
+            atoms,
+        })
+    }
+
+    /// Answers requests for the selections until we don't own any of them anymore
+    fn serve(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        while !self.selections.is_empty() {
+            match self.conn.wait_for_event() {
+                Ok(xcb::Event::X(x::Event::SelectionRequest(ev))) => self.answer(&ev)?,
+                Ok(xcb::Event::X(x::Event::SelectionClear(ev))) => {
+                    self.selections.retain(|&s| s != ev.selection());
+                }
+                Ok(_) => {}
+                // Requestors may vanish before we answer them
+                Err(xcb::Error::Protocol(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn answer(&self, ev: &x::SelectionRequestEvent) -> Result<(), Box<dyn std::error::Error>> {
+        // Obsolete clients don't name a property, the target is used instead
+        let property = match ev.property() {
+            x::ATOM_NONE => ev.target(),
+            property => property,
+        };
+
+        // Links are short enough to never need INCR transfers
+        let answered = if ev.target() == self.atoms.targets {
+            let mut targets = vec![self.atoms.targets];
+            targets.extend(&self.atoms.text);
+
+            self.conn.send_request(&x::ChangeProperty {
+                mode: x::PropMode::Replace,
+                window: ev.requestor(),
+                property,
+                r#type: x::ATOM_ATOM,
+                data: &targets,
+            });
+            true
+        } else if self.atoms.text.contains(&ev.target()) {
+            // `TEXT` lets the owner choose the type, the others name it
+            let r#type = match ev.target() {
+                target if target == self.atoms.text_any => self.atoms.text[0],
+                target => target,
+            };
+
+            self.conn.send_request(&x::ChangeProperty {
+                mode: x::PropMode::Replace,
+                window: ev.requestor(),
+                property,
+                r#type,
+                data: self.text.as_bytes(),
+            });
+            true
+        } else {
+            false
+        };
+
+        self.conn.send_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(ev.requestor()),
+            event_mask: x::EventMask::empty(),
+            event: &x::SelectionNotifyEvent::new(
+                ev.time(),
+                ev.requestor(),
+                ev.selection(),
+                ev.target(),
+                if answered { property } else { x::ATOM_NONE },
+            ),
+        });
+        self.conn.flush()?;
+
+        Ok(())
+    }
+}
+
+fn intern_atom(conn: &xcb::Connection, name: &str) -> Result<x::Atom, Box<dyn std::error::Error>> {
+    let reply = conn.wait_for_reply(conn.send_request(&x::InternAtom {
+        only_if_exists: false,
+        name: name.as_bytes(),
+    }))?;
+
+    Ok(reply.atom())
 }
 
 pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
@@ -40,31 +215,7 @@ pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>>
             data: v,
         };
     } else if let Ok(clipboard_content) = clipboard.get_text() {
-        let content_type;
-
-        if clipboard_content.starts_with("file://") {
-            let path = PathBuf::from(&clipboard_content[7..]);
-            path.try_exists()?;
-
-            content_type = guess_path_content(&path);
-
-            let data = std::fs::read(path)?;
-
-            content = ClipboardContent { content_type, data };
-        } else {
-            // Check if the content is a valid url
-            if let Ok(_) = url::Url::parse(&clipboard_content) {
-                // Let the backend know that this is a url
-                content_type = "text/uri-list".to_owned();
-            } else {
-                content_type = "text/plain".to_owned();
-            }
-
-            content = ClipboardContent {
-                content_type,
-                data: clipboard_content.into_bytes(),
-            };
-        }
+        content = text_content(clipboard_content)?;
     } else {
         // No content in clipboard
         return Err(Box::new(std::io::Error::new(
@@ -75,3 +226,14 @@ pub fn read_clipboard() -> Result<ClipboardContent, Box<dyn std::error::Error>>
 
     Ok(content)
 }
+
+/// Reads the primary selection, the text that was last selected
+pub fn read_selection() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+    let text = Clipboard::new()?
+        .get()
+        .clipboard(LinuxClipboardKind::Primary)
+        .text()
+        .map_err(|_| "Nothing is selected")?;
+
+    text_content(text)
+}
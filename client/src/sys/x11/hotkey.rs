@@ -1,59 +1,450 @@
-// we import the necessary modules (only the core X module in this application).
-use xcb::x;
-// we need to import the `Xid` trait for the `resource_id` call down there.
-use xcb::x::ModMask;
-// Many xcb functions return a `xcb::Result` or compatible result.
+use std::convert::Infallible;
+use std::thread;
+use std::time::Duration;
 
-pub fn create_listener<F>(callback: F) -> Result<(), Box<dyn std::error::Error>>
+use xcb::x::{self, Keycode, ModMask};
+use xcb::xkb;
+
+use crate::hotkey::{Hotkey, Key};
+
+/// Keysym of the NumLock key, used to find the modifier it is mapped to
+const XK_NUM_LOCK: u32 = 0xff7f;
+
+/// Delay before reconnecting to the X server, doubled after every failed attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Grabs every hotkey and calls `callback` with the index of the one that was pressed
+///
+/// If the connection to the X server drops, the hotkeys are grabbed again once it is back
+pub fn create_listener<F>(hotkeys: &[Hotkey], callback: F) -> Result<(), Box<dyn std::error::Error>>
 where
-    F: Fn() -> Result<(), Box<dyn std::error::Error>>,
+    F: Fn(usize),
 {
-    // Connect to the X server.
-    let (conn, screen_num) = xcb::Connection::connect(None)?;
+    let (mut conn, mut root) = connect()?;
+    let mut delay = RECONNECT_DELAY;
+
+    loop {
+        let Err(e) = listen(&conn, root, hotkeys, &callback);
+
+        // Anything but a broken connection, like a hotkey that can't be grabbed, is fatal
+        if conn.has_error().is_ok() {
+            return Err(e);
+        }
+        eprintln!("Lost the connection to the X server: {e}");
+
+        (conn, root) = loop {
+            eprintln!("Reconnecting in {}s", delay.as_secs());
+            thread::sleep(delay);
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+
+            match connect() {
+                Ok(connection) => break connection,
+                Err(e) => eprintln!("Unable to connect to the X server: {e}"),
+            }
+        };
+        delay = RECONNECT_DELAY;
+    }
+}
+
+/// Connects to the X server, the keymap is read through XKB
+fn connect() -> Result<(xcb::Connection, x::Window), Box<dyn std::error::Error>> {
+    let (conn, screen_num) =
+        xcb::Connection::connect_with_extensions(None, &[xcb::Extension::Xkb], &[])?;
+    conn.wait_for_reply(conn.send_request(&xkb::UseExtension {
+        wanted_major: 1,
+        wanted_minor: 0,
+    }))?;
+
+    // Get notified when the keyboard layout changes, as that changes the keycodes
+    conn.check_request(conn.send_request_checked(&xkb::SelectEvents {
+        device_spec: xkb::Id::UseCoreKbd as xkb::DeviceSpec,
+        affect_which: xkb::EventType::MAP_NOTIFY,
+        clear: xkb::EventType::empty(),
+        select_all: xkb::EventType::MAP_NOTIFY,
+        affect_map: xkb::MapPart::KEY_TYPES | xkb::MapPart::KEY_SYMS,
+        map: xkb::MapPart::KEY_TYPES | xkb::MapPart::KEY_SYMS,
+        details: &[],
+    }))?;
 
     // Fetch the `x::Setup` and get the main `x::Screen` object.
-    let setup = conn.get_setup();
-    let screen = setup.roots().nth(screen_num as usize).unwrap();
-
-    let cookie = conn.send_request_checked(&x::GrabKey {
-        owner_events: true,
-        grab_window: screen.root(),
-        modifiers: (ModMask::CONTROL | ModMask::N2),
-        key: 30,
-        pointer_mode: x::GrabMode::Async,
-        keyboard_mode: x::GrabMode::Async,
-    });
-    // We now check if the window creation worked.
-    // A cookie can't be cloned; it is moved to the function.
-    conn.check_request(cookie)?;
-
-    // A second time to also catch the key events without mod 2
-    let cookie = conn.send_request_checked(&x::GrabKey {
-        owner_events: true,
-        grab_window: screen.root(),
-        modifiers: (ModMask::CONTROL),
-        key: 30,
-        pointer_mode: x::GrabMode::Async,
-        keyboard_mode: x::GrabMode::Async,
-    });
-    // We now check if the window creation worked.
-    // A cookie can't be cloned; it is moved to the function.
-    conn.check_request(cookie)?;
+    let root = conn
+        .get_setup()
+        .roots()
+        .nth(screen_num as usize)
+        .ok_or("The X server has no default screen")?
+        .root();
+
+    Ok((conn, root))
+}
+
+/// Handles events until something goes wrong
+fn listen<F>(
+    conn: &xcb::Connection,
+    root: x::Window,
+    hotkeys: &[Hotkey],
+    callback: &F,
+) -> Result<Infallible, Box<dyn std::error::Error>>
+where
+    F: Fn(usize),
+{
+    let (mut grabs, errors) = grab_hotkeys(conn, root, hotkeys)?;
+    if let Some(e) = errors.into_iter().next() {
+        return Err(e.into());
+    }
 
     // We enter the main event loop
     loop {
         match conn.wait_for_event()? {
-            xcb::Event::X(x::Event::KeyPress(_ev)) => {
-                callback()?;
+            xcb::Event::X(x::Event::KeyPress(ev)) => {
+                // Lock modifiers don't matter, they were grabbed in every combination
+                let state =
+                    ModMask::from_bits_truncate(ev.state().bits()) - ModMask::LOCK - grabs.num_lock;
+
+                if let Some(index) = grabs
+                    .keys
+                    .iter()
+                    .position(|&grab| grab == Some((ev.detail(), state)))
+                {
+                    callback(index);
+                }
 
-                // The Strg + U Key was pressed
                 conn.flush()?;
             }
-            xcb::Event::X(x::Event::ClientMessage(ev)) => {
-                // We have received a message from the server
-                if let x::ClientMessageData::Data32([_atom, ..]) = ev.data() {}
+            xcb::Event::X(x::Event::MappingNotify(_))
+            | xcb::Event::Xkb(xkb::Event::MapNotify(_)) => {
+                conn.check_request(conn.send_request_checked(&x::UngrabKey {
+                    key: x::GRAB_ANY,
+                    grab_window: root,
+                    modifiers: ModMask::ANY,
+                }))?;
+
+                // A hotkey missing from the new layout shouldn't take the others down
+                let errors;
+                (grabs, errors) = grab_hotkeys(conn, root, hotkeys)?;
+                for e in errors {
+                    eprintln!("{e}, it stays disabled until the layout changes again");
+                }
             }
             _ => {}
         }
     }
 }
+
+struct Grabs {
+    /// Keycode and modifiers of every hotkey in the order they were passed,
+    /// `None` if it couldn't be grabbed
+    keys: Vec<Option<(Keycode, ModMask)>>,
+    /// The modifier NumLock is mapped to
+    num_lock: ModMask,
+}
+
+/// Grabs the hotkeys with the keycodes of the current layout
+///
+/// Hotkeys that can't be grabbed are skipped, the reasons are returned next to the grabs
+fn grab_hotkeys(
+    conn: &xcb::Connection,
+    root: x::Window,
+    hotkeys: &[Hotkey],
+) -> Result<(Grabs, Vec<String>), Box<dyn std::error::Error>> {
+    let keymap = Keymap::load(conn)?;
+
+    let mut keys = Vec::new();
+    let mut errors = Vec::new();
+    for hotkey in hotkeys {
+        match grab_hotkey(conn, root, &keymap, hotkey) {
+            Ok(grab) => keys.push(Some(grab)),
+            Err(e) => {
+                keys.push(None);
+                errors.push(e);
+            }
+        }
+    }
+
+    let grabs = Grabs {
+        keys,
+        num_lock: keymap.num_lock,
+    };
+
+    Ok((grabs, errors))
+}
+
+fn grab_hotkey(
+    conn: &xcb::Connection,
+    root: x::Window,
+    keymap: &Keymap,
+    hotkey: &Hotkey,
+) -> Result<(Keycode, ModMask), String> {
+    let (keycode, level_modifiers) = keymap
+        .key(keysym(hotkey.key))
+        .ok_or_else(|| format!("{hotkey} can't be typed with the current keyboard layout"))?;
+    // Keys only found on a shifted level need the modifiers of that level as well
+    let modifiers = mod_mask(hotkey) | level_modifiers;
+
+    // Grab every combination of lock modifiers, otherwise the hotkey
+    // wouldn't fire while NumLock or CapsLock is active
+    for locks in lock_combinations(keymap.num_lock) {
+        let cookie = conn.send_request_checked(&x::GrabKey {
+            owner_events: true,
+            grab_window: root,
+            modifiers: modifiers | locks,
+            key: keycode,
+            pointer_mode: x::GrabMode::Async,
+            keyboard_mode: x::GrabMode::Async,
+        });
+
+        let error = match conn.check_request(cookie) {
+            Ok(()) => continue,
+            Err(xcb::ProtocolError::X(x::Error::Access(_), _)) => {
+                format!(
+                    "{hotkey} is already used by another application, choose a different hotkey"
+                )
+            }
+            Err(e) => format!("Unable to grab {hotkey}: {e:?}"),
+        };
+
+        // Don't leave the combinations grabbed so far behind
+        for locks in lock_combinations(keymap.num_lock) {
+            conn.send_request(&x::UngrabKey {
+                key: keycode,
+                grab_window: root,
+                modifiers: modifiers | locks,
+            });
+        }
+
+        return Err(error);
+    }
+
+    Ok((keycode, modifiers))
+}
+
+/// A keysym and the modifiers selecting its level,
+/// `None` if the level can only be reached through lock modifiers
+type Level = (u32, Option<ModMask>);
+
+struct Keymap {
+    /// Keycode and the levels of the first group
+    keys: Vec<(Keycode, Vec<Level>)>,
+    /// The modifier NumLock is mapped to
+    num_lock: ModMask,
+}
+
+impl Keymap {
+    fn load(conn: &xcb::Connection) -> Result<Self, Box<dyn std::error::Error>> {
+        let reply = conn.wait_for_reply(conn.send_request(&xkb::GetMap {
+            device_spec: xkb::Id::UseCoreKbd as xkb::DeviceSpec,
+            full: xkb::MapPart::KEY_TYPES | xkb::MapPart::KEY_SYMS,
+            partial: xkb::MapPart::empty(),
+            first_type: 0,
+            n_types: 0,
+            first_key_sym: 0,
+            n_key_syms: 0,
+            first_key_action: 0,
+            n_key_actions: 0,
+            first_key_behavior: 0,
+            n_key_behaviors: 0,
+            virtual_mods: xkb::VMod::empty(),
+            first_key_explicit: 0,
+            n_key_explicit: 0,
+            first_mod_map_key: 0,
+            n_mod_map_keys: 0,
+            first_v_mod_map_key: 0,
+            n_v_mod_map_keys: 0,
+        }))?;
+
+        // Modifiers selecting each level of every key type, level 0 needs none
+        let mut levels: Vec<Vec<Option<ModMask>>> = Vec::new();
+        for part in reply.map() {
+            if let xkb::GetMapReplyMap::KeyTypes(types) = part {
+                levels = types
+                    .iter()
+                    .map(|kt| {
+                        (0..kt.num_levels())
+                            .map(|level| {
+                                if level == 0 {
+                                    return Some(ModMask::empty());
+                                }
+                                kt.map()
+                                    .iter()
+                                    .filter(|e| e.active() && e.level() == level)
+                                    .map(|e| e.mods_mask())
+                                    .find(|mods| !mods.contains(ModMask::LOCK))
+                            })
+                            .collect()
+                    })
+                    .collect();
+            }
+        }
+
+        let mut keys = Vec::new();
+        for part in reply.map() {
+            if let xkb::GetMapReplyMap::KeySyms(maps) = part {
+                for (i, map) in maps.iter().enumerate() {
+                    let keycode = reply.first_key_sym() as usize + i;
+                    let kt_levels = levels.get(map.kt_index()[0] as usize);
+
+                    // Other groups can't be reached by a key grab, so only the first one counts
+                    let syms = map
+                        .syms()
+                        .iter()
+                        .take(map.width() as usize)
+                        .enumerate()
+                        .map(|(level, &sym)| {
+                            let modifiers = kt_levels.and_then(|l| l.get(level).copied().flatten());
+                            (sym, modifiers)
+                        })
+                        .collect();
+                    keys.push((keycode as Keycode, syms));
+                }
+            }
+        }
+
+        let mut keymap = Keymap {
+            keys,
+            num_lock: ModMask::empty(),
+        };
+
+        // NumLock is usually Mod2, but that's up to the modifier mapping
+        let modifiers = conn.wait_for_reply(conn.send_request(&x::GetModifierMapping {}))?;
+        let per_modifier = modifiers.keycodes().len() / 8;
+        if let Some((num_lock, _)) = keymap.key(XK_NUM_LOCK) {
+            if let Some(i) = modifiers.keycodes().iter().position(|&k| k == num_lock) {
+                keymap.num_lock = ModMask::from_bits_truncate(1 << (i / per_modifier));
+            }
+        }
+
+        Ok(keymap)
+    }
+
+    /// The key producing `keysym` on the lowest level, with the modifiers selecting that level
+    ///
+    /// Levels selected by NumLock, like the digits of the keypad, don't count
+    fn key(&self, keysym: u32) -> Option<(Keycode, ModMask)> {
+        self.keys
+            .iter()
+            .flat_map(|(keycode, syms)| {
+                syms.iter()
+                    .enumerate()
+                    .filter(|(_, (sym, _))| *sym == keysym)
+                    .filter_map(|(level, (_, modifiers))| {
+                        let modifiers = (*modifiers)?;
+                        let reachable = !modifiers.intersects(self.num_lock);
+                        reachable.then_some((level, *keycode, modifiers))
+                    })
+            })
+            .min_by_key(|(level, _, _)| *level)
+            .map(|(_, keycode, modifiers)| (keycode, modifiers))
+    }
+}
+
+fn mod_mask(hotkey: &Hotkey) -> ModMask {
+    let m = &hotkey.modifiers;
+    let mut mask = ModMask::empty();
+
+    for (set, modifier) in [
+        (m.ctrl, ModMask::CONTROL),
+        (m.shift, ModMask::SHIFT),
+        (m.alt, ModMask::N1),
+        (m.super_key, ModMask::N4),
+    ] {
+        if set {
+            mask |= modifier;
+        }
+    }
+
+    mask
+}
+
+fn lock_combinations(num_lock: ModMask) -> [ModMask; 4] {
+    [
+        ModMask::empty(),
+        ModMask::LOCK,
+        num_lock,
+        ModMask::LOCK | num_lock,
+    ]
+}
+
+/// The X11 keysym of a key, letters use their lowercase keysym
+fn keysym(key: Key) -> u32 {
+    match key {
+        Key::Char(c) => c as u32,
+        Key::Function(n) => 0xffbd + n as u32,
+        Key::Space => 0x0020,
+        Key::Enter => 0xff0d,
+        Key::Tab => 0xff09,
+        Key::Escape => 0xff1b,
+        Key::Backspace => 0xff08,
+        Key::Delete => 0xffff,
+        Key::Insert => 0xff63,
+        Key::Home => 0xff50,
+        Key::End => 0xff57,
+        Key::PageUp => 0xff55,
+        Key::PageDown => 0xff56,
+        Key::Left => 0xff51,
+        Key::Right => 0xff53,
+        Key::Up => 0xff52,
+        Key::Down => 0xff54,
+        Key::PrintScreen => 0xff61,
+        Key::Pause => 0xff13,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keysyms() {
+        let cases = [
+            (Key::Char('a'), 0x0061),
+            (Key::Char('z'), 0x007a),
+            (Key::Char('0'), 0x0030),
+            (Key::Char('9'), 0x0039),
+            (Key::Function(1), 0xffbe),
+            (Key::Function(12), 0xffc9),
+            (Key::Function(24), 0xffd5),
+            (Key::Space, 0x0020),
+            (Key::Enter, 0xff0d),
+            (Key::Escape, 0xff1b),
+            (Key::Delete, 0xffff),
+            (Key::PageUp, 0xff55),
+            (Key::Left, 0xff51),
+            (Key::PrintScreen, 0xff61),
+            (Key::Pause, 0xff13),
+        ];
+
+        for (key, expected) in cases {
+            assert_eq!(keysym(key), expected, "{key}");
+        }
+    }
+
+    #[test]
+    fn lowest_reachable_level_wins() {
+        let shift = Some(ModMask::SHIFT);
+        let keymap = Keymap {
+            keys: vec![
+                // '1' on the shifted level, like on a French layout
+                (10, vec![(0x0026, Some(ModMask::empty())), (0x0031, shift)]),
+                (11, vec![(0x0061, Some(ModMask::empty())), (0x0041, shift)]),
+                // The keypad only produces digits with NumLock
+                (
+                    87,
+                    vec![
+                        (0xff9c, Some(ModMask::empty())),
+                        (0xffb1, Some(ModMask::N2)),
+                    ],
+                ),
+                // A level only reachable through CapsLock
+                (12, vec![(0x0062, Some(ModMask::empty())), (0x0033, None)]),
+            ],
+            num_lock: ModMask::N2,
+        };
+
+        assert_eq!(keymap.key(0x0031), Some((10, ModMask::SHIFT)));
+        assert_eq!(keymap.key(0x0041), Some((11, ModMask::SHIFT)));
+        assert_eq!(keymap.key(0x0061), Some((11, ModMask::empty())));
+        assert_eq!(keymap.key(0xff9c), Some((87, ModMask::empty())));
+        assert_eq!(keymap.key(0x0033), None);
+        assert_eq!(keymap.key(0xffb1), None);
+    }
+}
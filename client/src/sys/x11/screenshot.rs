@@ -0,0 +1,53 @@
+use image::codecs::png::PngEncoder;
+use xcb::x;
+
+use crate::upload::ClipboardContent;
+
+/// Takes a screenshot of all screens as png
+pub fn take_screenshot() -> Result<ClipboardContent, Box<dyn std::error::Error>> {
+    let (conn, screen_num) = xcb::Connection::connect(None)?;
+    let setup = conn.get_setup();
+    let screen = setup.roots().nth(screen_num as usize).unwrap();
+
+    // Only the common layout of 8 bits per color in 32 bit pixels is supported
+    let depth = screen.root_depth();
+    let bits_per_pixel = setup
+        .pixmap_formats()
+        .iter()
+        .find(|f| f.depth() == depth)
+        .map(|f| f.bits_per_pixel());
+    if !matches!(depth, 24 | 32)
+        || bits_per_pixel != Some(32)
+        || setup.image_byte_order() != x::ImageOrder::LsbFirst
+    {
+        return Err(format!("Screenshots of {depth} bit screens aren't supported").into());
+    }
+
+    let (width, height) = (screen.width_in_pixels(), screen.height_in_pixels());
+    let reply = conn.wait_for_reply(conn.send_request(&x::GetImage {
+        format: x::ImageFormat::ZPixmap,
+        drawable: x::Drawable::Window(screen.root()),
+        x: 0,
+        y: 0,
+        width,
+        height,
+        plane_mask: u32::MAX,
+    }))?;
+
+    // Pixels are stored as BGRX
+    let pixels = reply
+        .data()
+        .chunks_exact(4)
+        .flat_map(|p| [p[2], p[1], p[0], 255])
+        .collect();
+    let img_buf = image::RgbaImage::from_raw(width as u32, height as u32, pixels)
+        .ok_or("Screenshot has an unexpected size")?;
+
+    let mut data = Vec::new();
+    img_buf.write_with_encoder(PngEncoder::new(&mut data))?;
+
+    Ok(ClipboardContent {
+        content_type: "image/png".to_owned(),
+        data,
+    })
+}
@@ -1,45 +1,185 @@
+use std::{thread, time::Duration};
+
 use crate::{
-    config::load_config,
+    cli::UploadArgs,
+    config::{load_config, Profile},
+    notify::notify_uploaded,
     sys::clipboard::{read_clipboard, set_clipboard},
 };
+use short_clip_lib::UploadOptions;
+
+/// Attempts of an upload that fails because of the network or the server
+const UPLOAD_ATTEMPTS: u32 = 4;
+/// Delay before retrying a failed upload, doubled after every attempt
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Longest `Retry-After` of the server that is waited for before giving up
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
 
 pub struct ClipboardContent {
     pub content_type: String,
     pub data: Vec<u8>,
 }
 
-pub fn upload_clipboard() -> Result<(), Box<dyn std::error::Error>> {
-    let content = read_clipboard()?;
+/// Uploads the clipboard and replaces it with the link
+pub fn upload_clipboard(args: &UploadArgs) -> Result<(), Box<dyn std::error::Error>> {
+    upload_content(args, read_clipboard()?)
+}
 
-    if let Some(link) = upload_contents(&content.data, &content.content_type) {
-        set_clipboard(&link)?;
-    } else {
-        eprintln!("Uploading failed");
-    }
+/// Uploads `content` and copies the link to the clipboard
+pub fn upload_content(
+    args: &UploadArgs,
+    content: ClipboardContent,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = load_config();
+    let profile = config.profile(args.profile.as_deref())?;
+    let options = config.upload_options(&profile, args)?;
+
+    let link = upload_contents(&profile, &content.data, &content.content_type, &options)
+        .map_err(|e| e.to_string())?;
+    set_clipboard(&link, config.copy_to_primary)?;
+    notify_uploaded(&link, &content);
 
     Ok(())
 }
 
-fn upload_contents(data: &[u8], content_type: &str) -> Option<String> {
-    let config = load_config();
+/// Uploads the data and returns its link, transient failures are retried with backoff
+fn upload_contents(
+    profile: &Profile,
+    data: &[u8],
+    content_type: &str,
+    options: &UploadOptions,
+) -> Result<String, short_clip_lib::Error> {
+    let client = profile.client()?;
 
-    let result = ureq::post(&config.host)
-        .set("authorization", &config.token)
-        .set("Content-Type", content_type)
-        .send_bytes(data);
+    retry(
+        || {
+            client
+                .upload(data, content_type, options)
+                .map(|resp| resp.url)
+        },
+        thread::sleep,
+    )
+}
+
+/// Runs `request` until it succeeds or fails for good, `sleep` waits between the attempts
+///
+/// If the server asks to wait with `Retry-After`, that delay is used instead of the backoff
+fn retry<T>(
+    mut request: impl FnMut() -> Result<T, short_clip_lib::Error>,
+    mut sleep: impl FnMut(Duration),
+) -> Result<T, short_clip_lib::Error> {
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 1;
 
-    match result {
-        Ok(resp) => {
-            if resp.status() != 201 {
-                eprintln!("{}", resp.status_text());
-                return None;
+    loop {
+        match request() {
+            Ok(result) => return Ok(result),
+            Err(e) if e.is_transient() && attempt < UPLOAD_ATTEMPTS => {
+                let wait = e.retry_after().unwrap_or(delay);
+                if wait > MAX_RETRY_AFTER {
+                    return Err(e);
+                }
+
+                eprintln!("{e}, retrying in {}s", wait.as_secs());
+                sleep(wait);
+                delay *= 2;
+                attempt += 1;
             }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use short_clip_lib::{Error, ErrorCode};
 
-            resp.header("Location").map(|s| s.to_owned())
+    use super::*;
+
+    fn api_error(code: ErrorCode, retry_after: Option<u64>) -> Error {
+        Error::Api {
+            status: code.status(),
+            code,
+            message: "failed".to_owned(),
+            retry_after: retry_after.map(Duration::from_secs),
         }
-        Err(e) => {
-            eprintln!("{e}");
-            return None;
+    }
+
+    /// Runs `retry` with requests answered by `responses`, returning the result,
+    /// the number of requests and the delays waited for
+    fn run(
+        mut responses: Vec<Result<&'static str, Error>>,
+    ) -> (Result<&'static str, Error>, usize, Vec<u64>) {
+        responses.reverse();
+        let mut requests = 0;
+        let mut delays = Vec::new();
+
+        let result = retry(
+            || {
+                requests += 1;
+                responses.pop().expect("Too many requests")
+            },
+            |d| delays.push(d.as_secs()),
+        );
+
+        (result, requests, delays)
+    }
+
+    #[test]
+    fn transient_failures_are_retried_with_backoff() {
+        let (result, requests, delays) = run(vec![
+            Err(api_error(ErrorCode::Internal, None)),
+            Err(api_error(ErrorCode::Internal, None)),
+            Ok("link"),
+        ]);
+        assert_eq!(result.unwrap(), "link");
+        assert_eq!(requests, 3);
+        assert_eq!(delays, [1, 2]);
+
+        let (result, requests, delays) = run((0..UPLOAD_ATTEMPTS)
+            .map(|_| Err(api_error(ErrorCode::Internal, None)))
+            .collect());
+        assert_eq!(result.unwrap_err().code(), Some(ErrorCode::Internal));
+        assert_eq!(requests, UPLOAD_ATTEMPTS as usize);
+        assert_eq!(delays, [1, 2, 4]);
+    }
+
+    #[test]
+    fn retry_after_is_honoured() {
+        let (result, requests, delays) = run(vec![
+            Err(api_error(ErrorCode::TooManyRequests, Some(7))),
+            Err(api_error(ErrorCode::Internal, None)),
+            Ok("link"),
+        ]);
+        assert!(result.is_ok());
+        assert_eq!(requests, 3);
+        // The backoff keeps growing while the server decides how long to wait
+        assert_eq!(delays, [7, 2]);
+
+        let (result, requests, delays) = run(vec![Err(api_error(
+            ErrorCode::TooManyRequests,
+            Some(MAX_RETRY_AFTER.as_secs() + 1),
+        ))]);
+        assert!(result.is_err());
+        assert_eq!(requests, 1);
+        assert!(delays.is_empty());
+    }
+
+    #[test]
+    fn permanent_failures_are_not_retried() {
+        for code in [
+            ErrorCode::BadRequest,
+            ErrorCode::Unauthorized,
+            ErrorCode::PayloadTooLarge,
+        ] {
+            let (result, requests, delays) = run(vec![Err(api_error(code, None))]);
+            assert_eq!(result.unwrap_err().code(), Some(code));
+            assert_eq!(requests, 1);
+            assert!(delays.is_empty());
         }
+
+        let (result, requests, _) = run(vec![Err(Error::InvalidResponse("broken".into()))]);
+        assert!(result.is_err());
+        assert_eq!(requests, 1);
     }
 }
@@ -0,0 +1,384 @@
+use serde::Serialize;
+
+use crate::db;
+use crate::storage::{self, storage};
+use crate::tokens::{generate_token, hash_token, TokenFile, TokenFileEntry, TokenLine};
+use crate::util::*;
+
+type AdminResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+const OPTIONS: &str = "Options:
+    max_bytes=<size> max_clips=<n> max_upload=<size>   Quota overrides
+    scopes=<scope>,...        upload, read-private, delete and admin
+    expires=<timestamp>       Unix timestamp or +<duration> like +30d
+    content_types=<mime>,...  Allowed mime types like image/*
+    max_ttl=<duration>        Maximum ttl of uploads like 1d";
+
+const USAGE: &str = "Usage: short-clip-server <command>
+
+Without a command the server is started.
+
+Commands:
+    user add <username> [key=value ...]   Create a user and print its client config
+    user remove <username>                Remove all tokens of a user
+    token add <username> [key=value ...]  Create an additional token for an existing user
+    token rotate <username>               Replace the tokens of a user with a new one
+    token list                            List all tokens without revealing them
+    clips purge --user <username>         Delete all clips uploaded by a user
+    clips reencrypt                       Encrypt all clips with the active key
+    metadata export <dir>                 Write the metadata of all clips to json files
+    metadata upgrade                      Rewrite all metadata in the latest format
+    key generate                          Add a new encryption key and make it the active one";
+
+/// Runs an admin command, `args` excludes the program name
+pub async fn run(args: &[String]) -> AdminResult {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+
+    match args.as_slice() {
+        ["user", "add", username, options @ ..] => user_add(username, options).await,
+        ["user", "remove", username] => user_remove(username).await,
+        ["token", "add", username, options @ ..] => token_add(username, options).await,
+        ["token", "rotate", username] => token_rotate(username).await,
+        ["token", "list"] => token_list().await,
+        ["clips", "purge", "--user", username] => clips_purge(username).await,
+        ["clips", "reencrypt"] => clips_reencrypt().await,
+        ["metadata", "export", dir] => metadata_export(dir).await,
+        ["metadata", "upgrade"] => metadata_upgrade().await,
+        ["key", "generate"] => key_generate(),
+        ["help" | "--help" | "-h"] => {
+            println!("{USAGE}\n\n{OPTIONS}");
+            Ok(())
+        }
+        _ => {
+            eprintln!("{USAGE}\n\n{OPTIONS}");
+            std::process::exit(2);
+        }
+    }
+}
+
+async fn user_add(username: &str, options: &[&str]) -> AdminResult {
+    let mut file = TokenFile::read().await?;
+    if file.entries().any(|e| e.username == username) {
+        return Err(format!("User {username} already exists").into());
+    }
+
+    let token = generate_token()?;
+    let entry = TokenFileEntry::new(&hash_token(&token), username, resolve_options(options)?)?;
+
+    file.lines.push(TokenLine::Entry(entry));
+    file.write().await?;
+
+    eprintln!("Added user {username}, save this config as shortclip-config.json:");
+    print_client_config(&token)
+}
+
+async fn token_add(username: &str, options: &[&str]) -> AdminResult {
+    let mut file = TokenFile::read().await?;
+    if !file.entries().any(|e| e.username == username) {
+        return Err(format!("User {username} doesn't exist").into());
+    }
+
+    let token = generate_token()?;
+    let entry = TokenFileEntry::new(&hash_token(&token), username, resolve_options(options)?)?;
+
+    file.lines.push(TokenLine::Entry(entry));
+    file.write().await?;
+
+    eprintln!("Added token for {username}, save this config as shortclip-config.json:");
+    print_client_config(&token)
+}
+
+async fn user_remove(username: &str) -> AdminResult {
+    let mut file = TokenFile::read().await?;
+    let count = file.entries().filter(|e| e.username == username).count();
+    if count == 0 {
+        return Err(format!("User {username} doesn't exist").into());
+    }
+
+    file.lines
+        .retain(|l| !matches!(l, TokenLine::Entry(e) if e.username == username));
+    file.write().await?;
+
+    eprintln!("Removed {count} token(s) of {username}, their clips were kept");
+    eprintln!("Use `clips purge --user {username}` to delete them");
+
+    Ok(())
+}
+
+async fn token_rotate(username: &str) -> AdminResult {
+    let mut file = TokenFile::read().await?;
+    let position = file
+        .lines
+        .iter()
+        .position(|l| matches!(l, TokenLine::Entry(e) if e.username == username))
+        .ok_or(format!("User {username} doesn't exist"))?;
+
+    // The new token takes the place and the options of the first one
+    let token = generate_token()?;
+    let options = match &file.lines[position] {
+        TokenLine::Entry(e) => e.options.clone(),
+        TokenLine::Other(_) => unreachable!(),
+    };
+    file.lines[position] =
+        TokenLine::Entry(TokenFileEntry::new(&hash_token(&token), username, options)?);
+
+    // Revoke all other tokens of this user
+    let mut first = true;
+    file.lines.retain(|l| match l {
+        TokenLine::Entry(e) if e.username == username => std::mem::take(&mut first),
+        _ => true,
+    });
+    file.write().await?;
+
+    eprintln!("Rotated token of {username}, save this config as shortclip-config.json:");
+    print_client_config(&token)
+}
+
+async fn token_list() -> AdminResult {
+    let file = TokenFile::read().await?;
+
+    for entry in file.entries() {
+        let status = if entry.entry().is_expired() {
+            "expired"
+        } else {
+            "active"
+        };
+
+        println!(
+            "{}\t{}\t{status}\t{}",
+            entry.username,
+            entry.kind(),
+            entry.options.join(" ")
+        );
+    }
+
+    Ok(())
+}
+
+async fn clips_purge(username: &str) -> AdminResult {
+    storage::init()?;
+    db::init()?;
+
+    let mut count = 0;
+    for id in db::ids_by_author(username).await? {
+        db::delete(&id).await?;
+        storage().delete(&id).await?;
+        count += 1;
+    }
+
+    eprintln!("Deleted {count} clip(s) of {username}");
+
+    Ok(())
+}
+
+/// Rewrites every clip with the active key, also encrypting clips stored in plaintext
+async fn clips_reencrypt() -> AdminResult {
+    if std::env::var(storage::KEYS_VAR).is_err() {
+        return Err(format!("Set {} to the path of the key file", storage::KEYS_VAR).into());
+    }
+    storage::init()?;
+    db::init()?;
+
+    let mut count = 0;
+    for key in storage().list().await? {
+        // Skip objects deleted in the meantime
+        if let Some(data) = storage().get(&key, None).await? {
+            storage().put(&key, &data).await?;
+            count += 1;
+        }
+    }
+    for (id, metadata) in db::all().await? {
+        db::update(&id, &metadata).await?;
+    }
+
+    eprintln!("Re-encrypted {count} object(s) and the metadata of all clips");
+
+    Ok(())
+}
+
+/// Writes the metadata of every clip to `<dir>/<id>.json`
+///
+/// Copying the files into the `contents` directory restores them on the next start
+async fn metadata_export(dir: &str) -> AdminResult {
+    storage::init()?;
+    db::init()?;
+    tokio::fs::create_dir_all(dir).await?;
+
+    let clips = db::all().await?;
+    for (id, metadata) in &clips {
+        let path = std::path::Path::new(dir).join(format!("{id}.json"));
+        tokio::fs::write(path, metadata.to_string()?).await?;
+    }
+
+    eprintln!("Exported the metadata of {} clip(s) to {dir}", clips.len());
+
+    Ok(())
+}
+
+/// Rewrites all metadata in the latest version, including leftover metadata files
+async fn metadata_upgrade() -> AdminResult {
+    storage::init()?;
+    db::init()?;
+
+    let migrated = db::migrate().await?;
+    let upgraded = db::upgrade_all().await?;
+    eprintln!(
+        "Moved the metadata of {migrated} clip(s) into the metadata store and upgraded {upgraded} clip(s) to version {}",
+        shared::METADATA_VERSION
+    );
+
+    Ok(())
+}
+
+fn key_generate() -> AdminResult {
+    let path = std::env::var(storage::KEYS_VAR)
+        .map_err(|_| format!("Set {} to the path of the key file", storage::KEYS_VAR))?;
+
+    let id = storage::generate_key(std::path::Path::new(&path))?;
+    eprintln!("Added key {id} to {path}, restart the server to start using it");
+
+    Ok(())
+}
+
+/// Turns relative expiry times like `expires=+30d` into unix timestamps
+fn resolve_options(options: &[&str]) -> Result<Vec<String>, String> {
+    options
+        .iter()
+        .map(|option| match option.strip_prefix("expires=+") {
+            Some(duration) => {
+                let duration = parse_duration(duration)
+                    .ok_or(format!("Invalid duration for expires: {duration}"))?;
+
+                let expires = unix_time()
+                    .checked_add(duration)
+                    .ok_or(format!("Duration for expires is too long: {duration}"))?;
+
+                Ok(format!("expires={expires}"))
+            }
+            None => Ok(option.to_string()),
+        })
+        .collect()
+}
+
+/// The `shortclip-config.json` a client needs
+#[derive(Serialize)]
+struct ClientConfig<'a> {
+    token: &'a str,
+    host: &'a str,
+}
+
+fn print_client_config(token: &str) -> AdminResult {
+    let host = match std::env::var("HOST") {
+        Ok(host) => format!("https://{host}"),
+        Err(_) => "http://localhost:3000".to_owned(),
+    };
+
+    let config = ClientConfig { token, host: &host };
+    println!("{}", serde_json::to_string_pretty(&config)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::testing::*;
+
+    /// The commands rewrite the tokens file of the test server, one at a time
+    static TOKENS_FILE: Mutex<()> = Mutex::const_new(());
+
+    async fn entries(username: &str) -> Vec<(String, Vec<String>)> {
+        TokenFile::read()
+            .await
+            .unwrap()
+            .entries()
+            .filter(|e| e.username == username)
+            .map(|e| (e.secret.clone(), e.options.clone()))
+            .collect()
+    }
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_owned()).collect()
+    }
+
+    #[test]
+    fn relative_expiry() {
+        let options = resolve_options(&["expires=+1d", "scopes=upload", "expires=5"]).unwrap();
+
+        let expires: u64 = options[0]
+            .strip_prefix("expires=")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(expires.abs_diff(unix_time() + 86400) <= 1);
+        assert_eq!(options[1..], ["scopes=upload", "expires=5"]);
+
+        assert_eq!(
+            resolve_options(&["expires=+soon"]).unwrap_err(),
+            "Invalid duration for expires: soon"
+        );
+        assert!(resolve_options(&[&format!("expires=+{}", u64::MAX)]).is_err());
+    }
+
+    #[tokio::test]
+    async fn users_and_tokens() {
+        setup().await;
+        let _guard = TOKENS_FILE.lock().await;
+
+        run(&args("user add admin-cli max_clips=5 expires=+1h"))
+            .await
+            .unwrap();
+        let added = entries("admin-cli").await;
+        assert_eq!(added.len(), 1);
+        // Only the hash of the token is stored
+        assert!(added[0].0.starts_with("sha256:"));
+        assert_eq!(added[0].1[0], "max_clips=5");
+        assert!(added[0].1[1]
+            .strip_prefix("expires=")
+            .unwrap()
+            .parse::<u64>()
+            .is_ok());
+
+        assert!(run(&args("user add admin-cli")).await.is_err());
+        assert!(run(&args("user add admin-cli2 colour=blue")).await.is_err());
+        assert!(run(&args("token add admin-cli2")).await.is_err());
+        assert!(entries("admin-cli2").await.is_empty());
+
+        run(&args("token add admin-cli scopes=upload"))
+            .await
+            .unwrap();
+        run(&args("token add admin-cli")).await.unwrap();
+        assert_eq!(entries("admin-cli").await.len(), 3);
+        run(&args("token list")).await.unwrap();
+
+        // Rotating keeps the options of the first token and revokes the others
+        run(&args("token rotate admin-cli")).await.unwrap();
+        let rotated = entries("admin-cli").await;
+        assert_eq!(rotated.len(), 1);
+        assert_ne!(rotated[0].0, added[0].0);
+        assert_eq!(rotated[0].1, added[0].1);
+
+        run(&args("user remove admin-cli")).await.unwrap();
+        assert!(entries("admin-cli").await.is_empty());
+        assert!(run(&args("user remove admin-cli")).await.is_err());
+        assert!(run(&args("token rotate admin-cli")).await.is_err());
+
+        // Every other user is kept
+        assert!(!entries("admin").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn purge_clips_of_a_user() {
+        let ip = new_ip();
+        let purged = uploaded_id(upload(ip, "purged", "purged clip", &[]).await).await;
+        let kept = uploaded_id(upload(ip, "admin", "kept clip", &[]).await).await;
+
+        run(&args("clips purge --user purged")).await.unwrap();
+
+        assert_eq!(get(ip, &format!("/{purged}"), None).await.status(), 404);
+        assert!(storage().head(&purged).await.unwrap().is_none());
+        assert_eq!(get(ip, &format!("/{kept}"), None).await.status(), 200);
+    }
+}
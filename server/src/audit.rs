@@ -0,0 +1,140 @@
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use hyper::Method;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::ratelimit::RateLimiter;
+use crate::tokens::AuthError;
+use crate::util::unix_time;
+
+/// Entries waiting to be written before new ones are dropped
+const QUEUE_SIZE: usize = 1024;
+
+static AUDIT_QUEUE: OnceLock<mpsc::Sender<String>> = OnceLock::new();
+static AUDIT_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Opens the file set through the `AUTH_AUDIT_LOG` environment variable
+/// and spawns the task appending entries to it
+pub async fn init() -> Result<(), String> {
+    let Ok(path) = std::env::var("AUTH_AUDIT_LOG") else {
+        return Ok(());
+    };
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+        .map_err(|e| format!("Unable to open audit log {path}: {e}"))?;
+
+    let (sender, mut receiver) = mpsc::channel::<String>(QUEUE_SIZE);
+    let _ = AUDIT_QUEUE.set(sender);
+
+    tokio::spawn(async move {
+        while let Some(line) = receiver.recv().await {
+            if let Err(e) = file.write_all(line.as_bytes()).await {
+                eprintln!("Unable to write audit log: {e}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Sends the entries to the returned receiver instead of a file
+#[cfg(test)]
+pub fn capture() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+    AUDIT_QUEUE
+        .set(sender)
+        .expect("Audit log already initialised");
+
+    receiver
+}
+
+/// Records a failed authentication attempt
+///
+/// Entries are always written to stderr and additionally appended to the audit log.
+/// Each client IP is limited to 60 entries per minute so a flood of requests can't fill the disk
+pub fn auth_failure(ip: IpAddr, method: &Method, path: &str, reason: AuthError) {
+    let limiter = AUDIT_LIMITER.get_or_init(|| RateLimiter::new(60.0, 20.0));
+    if limiter.check(&ip.to_string(), 1.0).is_err() {
+        return;
+    }
+
+    let line = format!(
+        "time={} event=auth_failure ip={ip} method={method} path={path:?} reason={:?}",
+        unix_time(),
+        reason.to_string()
+    );
+
+    eprintln!("[audit] {line}");
+
+    if let Some(queue) = AUDIT_QUEUE.get() {
+        if queue.try_send(format!("{line}\n")).is_err() {
+            eprintln!("Audit log queue is full, dropping entry");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::Full;
+    use hyper::body::Bytes;
+    use hyper::header::AUTHORIZATION;
+    use hyper::Method;
+
+    use crate::testing::*;
+
+    #[tokio::test]
+    async fn rejected_tokens_are_audited() {
+        let ip = new_ip();
+
+        get(ip, "/clips", Some("unknown")).await;
+        let req = request(Method::DELETE, "/abc", None)
+            .header(AUTHORIZATION, "Basic abc")
+            .body(Full::<Bytes>::default())
+            .unwrap();
+        send(ip, req).await;
+        get(ip, "/usage", Some("expired")).await;
+
+        let log = audit_log(ip);
+        assert_eq!(log.len(), 3);
+        assert!(log[0].contains(&format!(
+            "event=auth_failure ip={ip} method=GET path=\"/clips\" reason=\"invalid token\""
+        )));
+        assert!(log[1]
+            .contains("method=DELETE path=\"/abc\" reason=\"malformed Authorization header\""));
+        assert!(log[2].contains("reason=\"expired token\""));
+    }
+
+    #[tokio::test]
+    async fn anonymous_requests_are_not_audited() {
+        let ip = new_ip();
+        let id =
+            uploaded_id(upload(ip, "other", "private", &[("visibility", "private")]).await).await;
+
+        assert_eq!(get(ip, &format!("/{id}"), None).await.status(), 404);
+        assert_eq!(get(ip, "/usage", None).await.status(), 401);
+        assert_eq!(get(ip, "/usage", Some("other")).await.status(), 200);
+
+        assert!(audit_log(ip).is_empty());
+    }
+
+    #[tokio::test]
+    async fn entries_are_limited_per_ip() {
+        let ip = new_ip();
+
+        for _ in 0..25 {
+            let req = request(Method::GET, "/usage", None)
+                .header(AUTHORIZATION, "malformed")
+                .body(Full::<Bytes>::default())
+                .unwrap();
+            assert_eq!(send(ip, req).await.status(), 401);
+        }
+
+        assert_eq!(audit_log(ip).len(), 20);
+    }
+}
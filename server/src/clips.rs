@@ -0,0 +1,74 @@
+use std::net::SocketAddr;
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Request, Response};
+use shared::api::{routes, ClipInfo, ClipList};
+
+use crate::db;
+use crate::tokens::{authenticate, Scope};
+use crate::util::*;
+
+/// Lists the clips of the authenticated user
+pub async fn handle_list<B>(
+    req: Request<B>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
+    let entry = match authenticate(&req, client_ip(&req, remote_addr)).await {
+        Ok(e) => e,
+        Err(e) => return Ok(unauthorized(e)),
+    };
+
+    let clips = db::clips_of(&entry.username)
+        .await?
+        .iter()
+        .map(|(id, metadata, size)| ClipInfo::new(id, metadata, *size))
+        .collect();
+
+    json_response(&ClipList { clips })
+}
+
+/// Describes a single clip, only its author or an admin may see it
+pub async fn handle_info<B>(
+    req: Request<B>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
+    let entry = match authenticate(&req, client_ip(&req, remote_addr)).await {
+        Ok(e) => e,
+        Err(e) => return Ok(unauthorized(e)),
+    };
+
+    let id = match req
+        .uri()
+        .path()
+        .strip_prefix(routes::CLIPS)
+        .and_then(clip_id)
+    {
+        Some(id) => id,
+        None => return Ok(not_found()),
+    };
+
+    // Clips of other users are reported as missing, so their ids can't be probed
+    let (metadata, size) = match db::get_with_size(id).await? {
+        Some((m, _)) if m.is_expired() => return Ok(not_found()),
+        Some((m, _)) if m.author != entry.username && !entry.has_scope(Scope::Admin) => {
+            return Ok(not_found())
+        }
+        Some(clip) => clip,
+        None => return Ok(not_found()),
+    };
+
+    json_response(&ClipInfo::new(id, &metadata, size))
+}
+
+fn json_response(
+    body: &impl serde::Serialize,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
+    let resp = Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .header(CACHE_CONTROL, "no-store")
+        .body(Full::new(Bytes::from(serde_json::to_string(body)?)))?;
+
+    Ok(resp)
+}
@@ -0,0 +1,680 @@
+use std::collections::HashMap;
+use std::io::Error;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use shared::{Metadata, METADATA_VERSION};
+
+use crate::storage::{metadata_key, open_value, seal_value, storage};
+use crate::util::unix_time;
+
+static DB: OnceLock<Store> = OnceLock::new();
+
+/// Database file used if `DATABASE_PATH` isn't set
+const DEFAULT_PATH: &str = "./short_clip.db";
+
+/// The metadata is stored as JSON, encrypted like the clips if a key file is configured.
+/// The columns used for lookups are stored next to it
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS clips (
+    id TEXT PRIMARY KEY NOT NULL,
+    author TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER,
+    size INTEGER NOT NULL,
+    metadata BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS clips_author ON clips (author);
+CREATE INDEX IF NOT EXISTS clips_created_at ON clips (created_at);
+CREATE INDEX IF NOT EXISTS clips_expires_at ON clips (expires_at);
+";
+
+/// Author, size and expiry of every clip stored as object, loaded by the first quota check
+///
+/// Saves reading every metadata object on each upload. Only this process keeps it up to
+/// date, which is fine as the server relies on process local locks for quotas anyway
+static OBJECT_INDEX: tokio::sync::Mutex<Option<HashMap<String, IndexEntry>>> =
+    tokio::sync::Mutex::const_new(None);
+
+/// Where the metadata of clips is kept, either way only a single server process may use it
+enum Store {
+    /// An SQLite database
+    Sqlite(Mutex<Connection>),
+    /// `<id>.json` objects next to the clips
+    Storage,
+}
+
+/// What quotas need to know about a clip
+struct IndexEntry {
+    author: String,
+    size: u64,
+    expires_at: Option<u64>,
+}
+
+impl IndexEntry {
+    fn new(metadata: &Metadata, size: u64) -> Self {
+        Self {
+            author: metadata.author.clone(),
+            size,
+            expires_at: metadata.expires_at,
+        }
+    }
+}
+
+/// Sets up the metadata store selected through `METADATA_STORE`
+///
+/// `sqlite` (default) opens the database at `DATABASE_PATH`, creating it if necessary.
+/// `storage` keeps the metadata in the storage backend
+pub fn init() -> Result<(), Error> {
+    if DB.get().is_some() {
+        return Ok(());
+    }
+
+    let store = match std::env::var("METADATA_STORE").as_deref() {
+        Ok("storage") => Store::Storage,
+        Ok("sqlite") | Err(_) => Store::Sqlite(Mutex::new(open(&database_path())?)),
+        Ok(other) => return Err(Error::other(format!("Unknown metadata store {other}"))),
+    };
+
+    let _ = DB.set(store);
+
+    Ok(())
+}
+
+fn database_path() -> String {
+    std::env::var("DATABASE_PATH").unwrap_or(DEFAULT_PATH.to_owned())
+}
+
+fn open(path: &str) -> Result<Connection, Error> {
+    let conn = Connection::open(path)
+        .map_err(|e| Error::other(format!("Unable to open database {path}: {e}")))?;
+
+    conn.pragma_update(None, "journal_mode", "WAL")
+        .map_err(Error::other)?;
+    conn.execute_batch(SCHEMA).map_err(Error::other)?;
+
+    Ok(conn)
+}
+
+/// The database connection, `None` if the metadata is kept in the storage backend
+fn sqlite() -> Option<&'static Mutex<Connection>> {
+    match DB.get().expect("Database not initialised") {
+        Store::Sqlite(conn) => Some(conn),
+        Store::Storage => None,
+    }
+}
+
+/// Runs a query on a blocking thread, so SQLite doesn't stall the async runtime
+async fn query<T, F>(conn: &'static Mutex<Connection>, f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || f(&conn.lock().unwrap()))
+        .await
+        .map_err(Error::other)?
+        .map_err(Error::other)
+}
+
+fn decode(id: &str, blob: &[u8]) -> Result<Metadata, Error> {
+    Ok(Metadata::from_slice(&open_value(&metadata_key(id), blob)?)?)
+}
+
+fn encode(id: &str, metadata: &Metadata) -> Result<Vec<u8>, Error> {
+    seal_value(&metadata_key(id), metadata.to_string()?.as_bytes())
+}
+
+/// Metadata of a clip stored as object, `None` if it doesn't exist
+async fn get_object(id: &str) -> Result<Option<Metadata>, Error> {
+    let data = storage().get(&metadata_key(id), None).await?;
+
+    Ok(data.map(|d| Metadata::from_slice(&d)).transpose()?)
+}
+
+/// Stores the metadata of a clip as object, `size` is only used if the index is loaded
+async fn put_object(id: &str, metadata: &Metadata, size: Option<u64>) -> Result<(), Error> {
+    storage()
+        .put(&metadata_key(id), metadata.to_string()?.as_bytes())
+        .await?;
+
+    if let Some(index) = OBJECT_INDEX.lock().await.as_mut() {
+        let size = size.or(index.get(id).map(|e| e.size)).unwrap_or_default();
+        index.insert(id.to_owned(), IndexEntry::new(metadata, size));
+    }
+
+    Ok(())
+}
+
+async fn delete_object(id: &str) -> Result<(), Error> {
+    storage().delete(&metadata_key(id)).await?;
+
+    if let Some(index) = OBJECT_INDEX.lock().await.as_mut() {
+        index.remove(id);
+    }
+
+    Ok(())
+}
+
+/// Number of bytes and clips stored as objects per author, expired clips are not counted
+async fn object_usage(author: Option<&str>, now: u64) -> Result<Vec<(String, u64, u64)>, Error> {
+    let mut index = OBJECT_INDEX.lock().await;
+    if index.is_none() {
+        let clips = all_objects().await?;
+        *index = Some(
+            clips
+                .into_iter()
+                .map(|(id, metadata, size)| (id, IndexEntry::new(&metadata, size)))
+                .collect(),
+        );
+    }
+
+    let mut usage: HashMap<&str, (u64, u64)> = HashMap::new();
+    for entry in index.iter().flat_map(|index| index.values()) {
+        if entry.expires_at.is_some_and(|e| e < now) || author.is_some_and(|a| a != entry.author) {
+            continue;
+        }
+        let usage = usage.entry(&entry.author).or_default();
+        usage.0 += entry.size;
+        usage.1 += 1;
+    }
+
+    Ok(usage
+        .into_iter()
+        .map(|(author, (bytes, clips))| (author.to_owned(), bytes, clips))
+        .collect())
+}
+
+/// Id, metadata and size of every clip stored as object, ordered by upload time
+///
+/// Every object has to be read, which is fine for the amount of clips a single instance holds
+async fn all_objects() -> Result<Vec<(String, Metadata, u64)>, Error> {
+    let mut clips = Vec::new();
+
+    for key in storage().list().await? {
+        let Some(id) = key.strip_suffix(".json") else {
+            continue;
+        };
+        // Skip clips deleted in the meantime
+        let (Some(metadata), Some(size)) = (get_object(id).await?, storage().head(id).await?)
+        else {
+            continue;
+        };
+
+        clips.push((id.to_owned(), metadata, size));
+    }
+    clips.sort_by_key(|(_, metadata, _)| metadata.created_at);
+
+    Ok(clips)
+}
+
+fn is_expired(metadata: &Metadata, now: u64) -> bool {
+    metadata.expires_at.is_some_and(|e| e < now)
+}
+
+/// Metadata of a clip, `None` if it doesn't exist
+pub async fn get(id: &str) -> Result<Option<Metadata>, Error> {
+    let Some(conn) = sqlite() else {
+        return get_object(id).await;
+    };
+
+    let owned_id = id.to_owned();
+    let blob: Option<Vec<u8>> = query(conn, move |conn| {
+        conn.query_row(
+            "SELECT metadata FROM clips WHERE id = ?1",
+            params![owned_id],
+            |row| row.get(0),
+        )
+        .optional()
+    })
+    .await?;
+
+    blob.map(|b| decode(id, &b)).transpose()
+}
+
+/// Metadata and size of a clip, `None` if it doesn't exist
+pub async fn get_with_size(id: &str) -> Result<Option<(Metadata, u64)>, Error> {
+    let Some(conn) = sqlite() else {
+        let Some(metadata) = get_object(id).await? else {
+            return Ok(None);
+        };
+        return Ok(storage().head(id).await?.map(|size| (metadata, size)));
+    };
+
+    let owned_id = id.to_owned();
+    let row: Option<(Vec<u8>, i64)> = query(conn, move |conn| {
+        conn.query_row(
+            "SELECT metadata, size FROM clips WHERE id = ?1",
+            params![owned_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+    })
+    .await?;
+
+    row.map(|(b, size)| Ok((decode(id, &b)?, size as u64)))
+        .transpose()
+}
+
+/// Adds a clip, `size` is the size of its data in bytes
+pub async fn insert(id: &str, metadata: &Metadata, size: u64) -> Result<(), Error> {
+    let Some(conn) = sqlite() else {
+        return put_object(id, metadata, Some(size)).await;
+    };
+
+    let id = id.to_owned();
+    let author = metadata.author.clone();
+    let created_at = metadata.created_at as i64;
+    let expires_at = metadata.expires_at.map(|e| e as i64);
+    let blob = encode(&id, metadata)?;
+    query(conn, move |conn| {
+        conn.execute(
+            "INSERT OR REPLACE INTO clips (id, author, created_at, expires_at, size, metadata)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![id, author, created_at, expires_at, size as i64, blob],
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Replaces the metadata of an existing clip
+pub async fn update(id: &str, metadata: &Metadata) -> Result<(), Error> {
+    let Some(conn) = sqlite() else {
+        return put_object(id, metadata, None).await;
+    };
+
+    let id = id.to_owned();
+    let author = metadata.author.clone();
+    let expires_at = metadata.expires_at.map(|e| e as i64);
+    let blob = encode(&id, metadata)?;
+    query(conn, move |conn| {
+        conn.execute(
+            "UPDATE clips SET author = ?2, expires_at = ?3, metadata = ?4 WHERE id = ?1",
+            params![id, author, expires_at, blob],
+        )
+    })
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete(id: &str) -> Result<(), Error> {
+    let Some(conn) = sqlite() else {
+        return delete_object(id).await;
+    };
+
+    let id = id.to_owned();
+    query(conn, move |conn| {
+        conn.execute("DELETE FROM clips WHERE id = ?1", params![id])
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Number of bytes and clips stored per author, expired clips are not counted
+pub async fn usage(author: Option<&str>) -> Result<Vec<(String, u64, u64)>, Error> {
+    let now = unix_time();
+
+    let Some(conn) = sqlite() else {
+        return object_usage(author, now).await;
+    };
+
+    let author = author.map(|a| a.to_owned());
+    query(conn, move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT author, SUM(size), COUNT(*) FROM clips
+            WHERE (expires_at IS NULL OR expires_at >= ?1) AND (?2 IS NULL OR author = ?2)
+            GROUP BY author",
+        )?;
+
+        let rows = stmt.query_map(params![now as i64, author], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)? as u64,
+                row.get::<_, i64>(2)? as u64,
+            ))
+        })?;
+
+        rows.collect()
+    })
+    .await
+}
+
+/// Ids of all clips uploaded by a user
+pub async fn ids_by_author(author: &str) -> Result<Vec<String>, Error> {
+    let Some(conn) = sqlite() else {
+        return Ok(all_objects()
+            .await?
+            .into_iter()
+            .filter(|(_, metadata, _)| metadata.author == author)
+            .map(|(id, _, _)| id)
+            .collect());
+    };
+
+    let author = author.to_owned();
+    query(conn, move |conn| {
+        let mut stmt = conn.prepare("SELECT id FROM clips WHERE author = ?1")?;
+        let rows = stmt.query_map(params![author], |row| row.get(0))?;
+
+        rows.collect()
+    })
+    .await
+}
+
+/// Id, metadata and size of the clips uploaded by a user, ordered by upload time
+///
+/// Expired clips are skipped
+pub async fn clips_of(author: &str) -> Result<Vec<(String, Metadata, u64)>, Error> {
+    let now = unix_time();
+
+    let Some(conn) = sqlite() else {
+        return Ok(all_objects()
+            .await?
+            .into_iter()
+            .filter(|(_, metadata, _)| metadata.author == author && !is_expired(metadata, now))
+            .collect());
+    };
+
+    let author = author.to_owned();
+    let rows: Vec<(String, Vec<u8>, i64)> = query(conn, move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, metadata, size FROM clips
+            WHERE author = ?1 AND (expires_at IS NULL OR expires_at >= ?2)
+            ORDER BY created_at",
+        )?;
+        let rows = stmt.query_map(params![author, now as i64], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+
+        rows.collect()
+    })
+    .await?;
+
+    rows.into_iter()
+        .map(|(id, blob, size)| Ok((id.clone(), decode(&id, &blob)?, size as u64)))
+        .collect()
+}
+
+/// Metadata of every clip, ordered by upload time
+pub async fn all() -> Result<Vec<(String, Metadata)>, Error> {
+    let Some(conn) = sqlite() else {
+        return Ok(all_objects()
+            .await?
+            .into_iter()
+            .map(|(id, metadata, _)| (id, metadata))
+            .collect());
+    };
+
+    let rows = all_rows(conn, "SELECT id, metadata FROM clips ORDER BY created_at").await?;
+
+    rows.into_iter()
+        .map(|(id, blob)| Ok((id.clone(), decode(&id, &blob)?)))
+        .collect()
+}
+
+/// Id and raw metadata of every row returned by `sql`
+async fn all_rows(
+    conn: &'static Mutex<Connection>,
+    sql: &'static str,
+) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    query(conn, move |conn| read_rows(conn, sql)).await
+}
+
+fn read_rows(conn: &Connection, sql: &str) -> rusqlite::Result<Vec<(String, Vec<u8>)>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    rows.collect()
+}
+
+/// Rewrites metadata stored in an older version in the latest one
+///
+/// Returns the number of upgraded clips
+pub async fn upgrade_all() -> Result<usize, Error> {
+    let Some(conn) = sqlite() else {
+        let mut count = 0;
+        for key in storage().list().await? {
+            let Some(id) = key.strip_suffix(".json") else {
+                continue;
+            };
+            let Some(json) = storage().get(&key, None).await? else {
+                continue;
+            };
+
+            if Metadata::stored_version(&json)? < METADATA_VERSION {
+                update(id, &Metadata::from_slice(&json)?).await?;
+                count += 1;
+            }
+        }
+
+        return Ok(count);
+    };
+
+    let mut count = 0;
+    for (id, blob) in all_rows(conn, "SELECT id, metadata FROM clips").await? {
+        let json = open_value(&metadata_key(&id), &blob)?;
+
+        if Metadata::stored_version(&json)? < METADATA_VERSION {
+            update(&id, &Metadata::from_slice(&json)?).await?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Checks that the database can be queried, the storage backend is checked separately
+pub async fn check() -> Result<(), Error> {
+    let Some(conn) = sqlite() else {
+        return Ok(());
+    };
+
+    query(conn, |conn| conn.query_row("SELECT 1", [], |_| Ok(()))).await
+}
+
+/// Moves metadata kept in the other store into the configured one
+///
+/// With a database the `<id>.json` objects used before it are moved into it,
+/// sidecars of clips whose data is gone are removed. Without one the clips of
+/// an existing database file are written as objects and the file is renamed to `<path>.migrated`.
+/// Returns the number of migrated clips
+pub async fn migrate() -> Result<usize, Error> {
+    if sqlite().is_none() {
+        return export_database().await;
+    }
+
+    let mut count = 0;
+
+    for key in storage().list().await? {
+        let Some(id) = key.strip_suffix(".json") else {
+            continue;
+        };
+        let Some(data) = storage().get(&key, None).await? else {
+            continue;
+        };
+
+        if let Some(size) = storage().head(id).await? {
+            insert(id, &Metadata::from_slice(&data)?, size).await?;
+            count += 1;
+        }
+        storage().delete(&key).await?;
+    }
+
+    Ok(count)
+}
+
+async fn export_database() -> Result<usize, Error> {
+    let path = database_path();
+    if !Path::new(&path).exists() {
+        return Ok(0);
+    }
+
+    let db_path = path.clone();
+    let rows = tokio::task::spawn_blocking(move || {
+        read_rows(&open(&db_path)?, "SELECT id, metadata FROM clips").map_err(Error::other)
+    })
+    .await
+    .map_err(Error::other)??;
+    for (id, blob) in &rows {
+        insert(id, &decode(id, blob)?, 0).await?;
+    }
+
+    tokio::fs::rename(&path, format!("{path}.migrated")).await?;
+
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::setup;
+
+    fn metadata(author: &str, created_at: u64, expires_at: Option<u64>) -> Metadata {
+        let mut metadata = Metadata::new(author, "text/plain", None).unwrap();
+        metadata.created_at = created_at;
+        metadata.expires_at = expires_at;
+
+        metadata
+    }
+
+    #[tokio::test]
+    async fn sqlite_store() {
+        setup().await;
+        let now = unix_time();
+        let author = "db-sqlite";
+
+        insert("db-sqlite-b", &metadata(author, now, None), 3)
+            .await
+            .unwrap();
+        insert("db-sqlite-a", &metadata(author, now - 10, None), 5)
+            .await
+            .unwrap();
+        insert("db-sqlite-c", &metadata(author, now, Some(now - 1)), 7)
+            .await
+            .unwrap();
+
+        let (found, size) = get_with_size("db-sqlite-a").await.unwrap().unwrap();
+        assert_eq!((found.author.as_str(), size), (author, 5));
+        assert!(get("db-sqlite-missing").await.unwrap().is_none());
+
+        // Expired clips are neither counted nor listed, but still belong to their author
+        assert_eq!(
+            usage(Some(author)).await.unwrap(),
+            [(author.to_owned(), 8, 2)]
+        );
+        let ids: Vec<_> = clips_of(author)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.0)
+            .collect();
+        assert_eq!(ids, ["db-sqlite-a", "db-sqlite-b"]);
+        let mut ids = ids_by_author(author).await.unwrap();
+        ids.sort();
+        assert_eq!(ids, ["db-sqlite-a", "db-sqlite-b", "db-sqlite-c"]);
+
+        let mut moved = found;
+        moved.author = "db-sqlite-other".to_owned();
+        moved.views = 3;
+        update("db-sqlite-a", &moved).await.unwrap();
+        assert_eq!(get("db-sqlite-a").await.unwrap().unwrap().views, 3);
+        assert_eq!(
+            usage(Some(author)).await.unwrap(),
+            [(author.to_owned(), 3, 1)]
+        );
+
+        for id in ["db-sqlite-a", "db-sqlite-b", "db-sqlite-c"] {
+            delete(id).await.unwrap();
+            assert!(get(id).await.unwrap().is_none());
+        }
+        assert!(usage(Some(author)).await.unwrap().is_empty());
+        check().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn object_store() {
+        setup().await;
+        let now = unix_time();
+        let author = "db-objects";
+        storage().put("db-object-a", b"12345").await.unwrap();
+        storage().put("db-object-b", b"123").await.unwrap();
+
+        put_object("db-object-b", &metadata(author, now, None), Some(3))
+            .await
+            .unwrap();
+        put_object("db-object-a", &metadata(author, now - 10, None), Some(5))
+            .await
+            .unwrap();
+        // Metadata without data is a clip that is being deleted
+        put_object("db-object-gone", &metadata(author, now, None), Some(1))
+            .await
+            .unwrap();
+        storage().delete("db-object-gone").await.unwrap();
+
+        assert_eq!(
+            get_object("db-object-a").await.unwrap().unwrap().author,
+            author
+        );
+        let ids: Vec<_> = all_objects()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.0)
+            .collect();
+        assert_eq!(ids, ["db-object-a", "db-object-b"]);
+
+        // The first quota check loads the index, later changes keep it up to date
+        assert_eq!(
+            object_usage(Some(author), now).await.unwrap(),
+            [(author.to_owned(), 8, 2)]
+        );
+
+        storage().put("db-object-c", b"1234567").await.unwrap();
+        put_object(
+            "db-object-c",
+            &metadata(author, now, Some(now - 1)),
+            Some(7),
+        )
+        .await
+        .unwrap();
+        storage().put("db-object-d", b"1").await.unwrap();
+        put_object("db-object-d", &metadata(author, now, None), Some(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            object_usage(Some(author), now).await.unwrap(),
+            [(author.to_owned(), 9, 3)]
+        );
+
+        let mut moved = metadata("db-objects-other", now - 10, None);
+        moved.views = 1;
+        put_object("db-object-a", &moved, None).await.unwrap();
+        assert_eq!(get_object("db-object-a").await.unwrap().unwrap().views, 1);
+        let mut all = object_usage(None, now).await.unwrap();
+        all.retain(|(a, _, _)| a.starts_with(author));
+        all.sort();
+        assert_eq!(
+            all,
+            [
+                (author.to_owned(), 4, 2),
+                ("db-objects-other".to_owned(), 5, 1)
+            ]
+        );
+
+        for id in [
+            "db-object-a",
+            "db-object-b",
+            "db-object-c",
+            "db-object-d",
+            "db-object-gone",
+        ] {
+            delete_object(id).await.unwrap();
+            storage().delete(id).await.unwrap();
+            assert!(get_object(id).await.unwrap().is_none());
+        }
+        assert!(object_usage(Some(author), now).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,73 @@
+use std::net::SocketAddr;
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::{Request, Response};
+
+use crate::db;
+use crate::storage::storage;
+use crate::tokens::{authenticate, Scope};
+use crate::util::*;
+
+/// Deletes a clip, only its author or an admin may do so
+pub async fn handle_delete<B>(
+    req: Request<B>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
+    let entry = match authenticate(&req, client_ip(&req, remote_addr)).await {
+        Ok(e) => e,
+        Err(e) => return Ok(unauthorized(e)),
+    };
+    if !entry.has_scope(Scope::Delete) {
+        return Ok(forbidden("Token is not allowed to delete clips"));
+    }
+
+    let id = match clip_id(req.uri().path()) {
+        Some(id) => id,
+        None => return Ok(not_found()),
+    };
+
+    // Clips of other users are reported as missing, so their ids can't be probed
+    match db::get(id).await? {
+        Some(m) if m.author == entry.username || entry.has_scope(Scope::Admin) => {}
+        _ => return Ok(not_found()),
+    }
+
+    db::delete(id).await?;
+    storage().delete(id).await?;
+
+    let resp = Response::builder()
+        .status(204) // "No Content" Status
+        .body(Full::new(Bytes::new()))?;
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::*;
+
+    #[tokio::test]
+    async fn only_authors_and_admins_delete_clips() {
+        let ip = new_ip();
+        let id = uploaded_id(upload(ip, "deleter", "delete me", &[]).await).await;
+        let path = format!("/{id}");
+
+        // Other users can't tell the clip exists
+        let resp = delete(ip, &path, Some("other")).await;
+        assert_eq!(resp.status(), 404);
+        assert_eq!(get(ip, &path, None).await.status(), 200);
+
+        assert_eq!(delete(ip, &path, None).await.status(), 401);
+        assert_eq!(delete(ip, &path, Some("uploader")).await.status(), 403);
+
+        assert_eq!(delete(ip, &path, Some("deleter")).await.status(), 204);
+        assert_eq!(get(ip, &path, None).await.status(), 404);
+        assert_eq!(delete(ip, &path, Some("deleter")).await.status(), 404);
+
+        let id = uploaded_id(upload(ip, "deleter", "admins delete everything", &[]).await).await;
+        let path = format!("/{id}");
+        assert_eq!(delete(ip, &path, Some("admin")).await.status(), 204);
+        assert_eq!(get(ip, &path, None).await.status(), 404);
+    }
+}
@@ -1,87 +1,480 @@
-use http_body_util::Full;
-use hyper::body::Bytes;
-use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION, X_CONTENT_TYPE_OPTIONS};
-use hyper::{Request, Response};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Body, Bytes};
+use hyper::header::{
+    ACCEPT_RANGES, CACHE_CONTROL, CONTENT_RANGE, CONTENT_TYPE, LOCATION, RANGE, VARY,
+    X_CONTENT_TYPE_OPTIONS,
+};
+use hyper::http::response::Builder;
+use hyper::{Method, Request, Response};
 
-use shared::Metadata;
+use shared::api::{headers, ErrorCode, URI_LIST};
+use shared::{Metadata, Visibility};
 
+use crate::db;
+use crate::encrypted::decrypt_page;
+use crate::password::{password_required, verify_password, wants_html};
+use crate::ratelimit::{download_limiter, not_found_cost, password_limiter};
+use crate::storage::storage;
+use crate::tokens::authenticate;
 use crate::util::*;
+use crate::views::{consume_view, is_preview_bot, reveal_page};
 
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use std::net::SocketAddr;
+use std::ops::Range;
+
+/// Password forms are tiny, anything larger is rejected
+const MAX_FORM_SIZE: usize = 4096;
+
+/// Serves `GET /<id>` as well as `POST /<id>` used by the password prompt page
+pub async fn handle_download<B>(
+    req: Request<B>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    let ip = client_ip(&req, remote_addr).to_string();
+    let limiter = download_limiter();
+
+    if let Err(retry_after) = limiter.check(&ip, 1.0) {
+        return Ok(too_many_requests(retry_after));
+    }
+
+    let resp = serve_download(req, remote_addr).await?;
+
+    // Unknown ids cost extra to slow down guessing
+    if resp.status() == 404 {
+        limiter.penalize(&ip, not_found_cost());
+    }
+
+    Ok(resp)
+}
+
+async fn serve_download<B>(
+    req: Request<B>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    let ip = client_ip(&req, remote_addr);
+    let (parts, body) = req.into_parts();
+    let req = Request::from_parts(parts, ());
+    let is_post = req.method() == Method::POST;
 
-pub async fn handle_download(
-    req: Request<hyper::body::Incoming>,
-) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
     // Extract id
-    let path = req.uri().path();
-    if path.len() < 2 {
-        return Ok(not_found());
+    let id = match clip_id(req.uri().path()) {
+        Some(id) => id,
+        None => return Ok(not_found()),
+    };
+
+    let metadata = match load_clip(id).await? {
+        Some(clip) => clip,
+        None => return Ok(not_found()),
+    };
+
+    // Private clips pretend not to exist for anyone not allowed to see them
+    if metadata.visibility == Visibility::Private {
+        let allowed = authenticate(&req, ip)
+            .await
+            .is_ok_and(|entry| entry.can_read_private(&metadata.author));
+
+        if !allowed {
+            return Ok(not_found());
+        }
     }
-    let id = &path[1..];
 
-    let mut dir_entries = tokio::fs::read_dir(content_path()).await?;
+    if let Some(password_hash) = &metadata.password_hash {
+        let password = match submitted_password(&req) {
+            Some(p) => p,
+            // The prompt page posts the password as a form
+            None if is_post => match Limited::new(body, MAX_FORM_SIZE).collect().await {
+                Ok(form) => form_urlencoded::parse(&form.to_bytes())
+                    .find(|(key, _)| key == "password")
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap_or_default(),
+                Err(_) => return Ok(bad_request("Form too large")),
+            },
+            None => return Ok(password_required(&req, id, false)),
+        };
 
-    while let Some(entry) = dir_entries.next_entry().await? {
-        if entry.file_name() != id {
-            continue;
+        // Wrong passwords are rate limited per client IP and clip. Every attempt is
+        // reserved before verifying, so concurrent guesses can't exceed the limit,
+        // and refunded if the password was right
+        let key = format!("{ip} {id}");
+        let limiter = password_limiter();
+        if let Err(retry_after) = limiter.check(&key, 1.0) {
+            return Ok(too_many_requests(retry_after));
         }
 
-        // Retrieve metadata to set Content-Type
-        let metadata = tokio::fs::read(entry.path().with_extension("json")).await?;
-        let metadata = Metadata::from_slice(&metadata)?;
+        if !verify_password(&password, password_hash).await {
+            return Ok(password_required(&req, id, true));
+        }
+        limiter.refund(&key, 1.0);
+    } else if is_post && metadata.max_views.is_none() {
+        return Ok(not_found());
+    }
 
-        // Check if this file is expired and delete it
-        if metadata.is_expired() {
-            tokio::fs::remove_file(entry.path()).await?;
-            tokio::fs::remove_file(entry.path().with_extension("json")).await?;
+    let (metadata, data) = if metadata.max_views.is_some() {
+        // Link previews and browsers opening the link must not use up a view,
+        // browsers get a page with a button that posts back to the clip instead
+        if is_preview_bot(&req) || (!is_post && wants_html(&req)) {
+            return Ok(reveal_page(id, &metadata));
+        }
 
-            return Ok(not_found());
+        match consume_view(id).await? {
+            Some(clip) => clip,
+            None => return Ok(not_found()),
+        }
+    } else {
+        // Only plain downloads support ranges, other clips are always served as a whole
+        let range = req.headers().get(RANGE).and_then(|v| v.to_str().ok());
+        if let Some(range) = range.filter(|_| supports_ranges(&metadata) && !is_post) {
+            return range_response(metadata, id, range).await;
         }
 
         // Read data
-        let mut file = File::open(entry.path()).await?;
+        let data = match storage().get(id, None).await? {
+            Some(d) => d,
+            None => return Ok(not_found()),
+        };
+        (metadata, data)
+    };
+
+    // Browsers decrypt the clip themselves, API clients get the raw ciphertext
+    if metadata.encrypted && wants_html(&req) {
+        return Ok(decrypt_page(&data));
+    }
 
-        let mut v: Vec<u8> = Vec::with_capacity(file.metadata().await?.len() as usize);
-        file.read_to_end(&mut v).await?;
+    clip_response(metadata, data, id, is_post)
+}
 
-        // Check if this is a URL
-        if metadata.content_type == "text/uri-list" {
-            let url = String::from_utf8(v)?;
+fn submitted_password<B>(req: &Request<B>) -> Option<String> {
+    req.headers()
+        .get(headers::PASSWORD)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned())
+}
 
-            let resp = Response::builder()
-                // Temporary Redirect
-                .status(307)
-                .header(LOCATION, &url)
-                .header(CONTENT_TYPE, metadata.content_type)
-                // Ignore browsers guessing the content type
-                .header(X_CONTENT_TYPE_OPTIONS, "nosniff")
-                .body(Full::new(Bytes::from(url.into_bytes())))?;
+/// Reads the metadata of a clip, deleting the clip if it is expired
+async fn load_clip(id: &str) -> Result<Option<Metadata>, Box<dyn std::error::Error>> {
+    // Retrieve metadata to set Content-Type
+    let metadata = match db::get(id).await? {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+
+    // Check if this file is expired and delete it
+    if metadata.is_expired() {
+        db::delete(id).await?;
+        storage().delete(id).await?;
+
+        return Ok(None);
+    }
+
+    Ok(Some(metadata))
+}
+
+/// Whether a clip can be downloaded in parts
+///
+/// View limited clips are consumed as a whole, redirects and encrypted clips are useless in parts
+fn supports_ranges(metadata: &Metadata) -> bool {
+    metadata.max_views.is_none() && !metadata.encrypted && metadata.content_type != URI_LIST
+}
+
+/// Parses a `Range` header containing a single byte range
+///
+/// Returns `Ok(None)` for anything else, in which case the whole clip is served,
+/// and `Err` if the range lies outside of the clip or is empty
+fn parse_range(value: &str, size: u64) -> Result<Option<Range<u64>>, ()> {
+    let Some((start, end)) = value
+        .strip_prefix("bytes=")
+        .filter(|r| !r.contains(','))
+        .and_then(|r| r.trim().split_once('-'))
+    else {
+        return Ok(None);
+    };
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // bytes=<start>-<end>, the end is inclusive
+        (Ok(start), Ok(end)) if start <= end => start..end.saturating_add(1).min(size),
+        // bytes=<start>-
+        (Ok(start), Err(_)) if end.is_empty() => start..size,
+        // bytes=-<suffix length>
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => size.saturating_sub(suffix)..size,
+        _ => return Ok(None),
+    };
+
+    if range.start >= size || range.is_empty() {
+        return Err(());
+    }
+
+    Ok(Some(range))
+}
+
+/// Serves the part of a clip requested through the `Range` header
+async fn range_response(
+    metadata: Metadata,
+    id: &str,
+    range: &str,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
+    let size = match storage().head(id).await? {
+        Some(size) => size,
+        None => return Ok(not_found()),
+    };
 
+    let range = match parse_range(range, size) {
+        Ok(Some(range)) => range,
+        Ok(None) => {
+            let data = match storage().get(id, None).await? {
+                Some(d) => d,
+                None => return Ok(not_found()),
+            };
+            return clip_response(metadata, data, id, false);
+        }
+        Err(()) => {
+            let resp = error_response(ErrorCode::RangeNotSatisfiable)
+                .header(CONTENT_RANGE, format!("bytes */{size}"))
+                .body(error_body(
+                    ErrorCode::RangeNotSatisfiable,
+                    "Range outside of the clip",
+                ))?;
             return Ok(resp);
         }
+    };
+
+    let data = match storage().get(id, Some(range.clone())).await? {
+        Some(d) => d,
+        None => return Ok(not_found()),
+    };
+
+    let resp = cache_headers(Response::builder(), &metadata)
+        .status(206) // "Partial Content" Status
+        .header(CONTENT_TYPE, &metadata.content_type)
+        .header(
+            CONTENT_RANGE,
+            format!("bytes {}-{}/{size}", range.start, range.end - 1),
+        )
+        .header(ACCEPT_RANGES, "bytes")
+        // Ignore browsers guessing the content type
+        .header(X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .body(Full::new(Bytes::from(data)))?;
 
-        // Guess viable file name
-        let mime: mime_guess::Mime = metadata.content_type.parse()?;
-        let extension = *mime_guess::get_mime_extensions(&mime)
-            .unwrap_or(&["bin"])
-            .first()
-            .unwrap_or(&"bin");
-        let _filename = format!("{id}.{extension}");
-
-        // Build response
-        let resp = Response::builder()
-            .header(CONTENT_TYPE, metadata.content_type)
-            // Enable caching
-            .header(CACHE_CONTROL, "max-age=31536000, immutable")
+    Ok(resp)
+}
+
+/// Builds the response containing the clip itself
+///
+/// `after_post` switches URL redirects to `303 See Other`,
+/// so browsers don't repeat the form submission at the target
+fn clip_response(
+    metadata: Metadata,
+    v: Vec<u8>,
+    id: &str,
+    after_post: bool,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
+    // Check if this is a URL
+    if metadata.content_type == URI_LIST {
+        let url = String::from_utf8(v)?;
+
+        let resp = cache_headers(Response::builder(), &metadata)
+            // Temporary Redirect
+            .status(if after_post { 303 } else { 307 })
+            .header(LOCATION, &url)
+            .header(CONTENT_TYPE, &metadata.content_type)
             // Ignore browsers guessing the content type
             .header(X_CONTENT_TYPE_OPTIONS, "nosniff")
-            // Auto download
-            //.header(CONTENT_DISPOSITION,format!("attachment; filename=\"{filename}\""),)
-            .body(Full::new(Bytes::from(v)))?;
+            .body(Full::new(Bytes::from(url.into_bytes())))?;
 
         return Ok(resp);
     }
 
-    Ok(not_found())
+    // Guess viable file name
+    let mime: mime_guess::Mime = metadata.content_type.parse()?;
+    let extension = *mime_guess::get_mime_extensions(&mime)
+        .unwrap_or(&["bin"])
+        .first()
+        .unwrap_or(&"bin");
+    let _filename = format!("{id}.{extension}");
+
+    // Build response
+    let mut builder = cache_headers(Response::builder(), &metadata);
+    if supports_ranges(&metadata) {
+        builder = builder.header(ACCEPT_RANGES, "bytes");
+    }
+    let resp = builder
+        .header(CONTENT_TYPE, &metadata.content_type)
+        // Ignore browsers guessing the content type
+        .header(X_CONTENT_TYPE_OPTIONS, "nosniff")
+        // Auto download
+        //.header(CONTENT_DISPOSITION,format!("attachment; filename=\"{filename}\""),)
+        .body(Full::new(Bytes::from(v)))?;
+
+    Ok(resp)
+}
+
+/// Adds the caching and indexing headers matching the visibility of a clip
+fn cache_headers(builder: Builder, metadata: &Metadata) -> Builder {
+    // Shared caches must never store private, password protected or view limited clips
+    if metadata.visibility == Visibility::Private
+        || metadata.password_hash.is_some()
+        || metadata.max_views.is_some()
+    {
+        return builder
+            .header(CACHE_CONTROL, "private, no-store")
+            .header(VARY, "authorization, clip-password")
+            .header("x-robots-tag", "noindex");
+    }
+
+    // Enable caching
+    let mut builder = builder.header(CACHE_CONTROL, "max-age=31536000, immutable");
+    if metadata.encrypted {
+        // Browsers get the decryption page for the same url
+        builder = builder.header(VARY, "accept");
+    }
+
+    match metadata.visibility {
+        Visibility::Unlisted => builder.header("x-robots-tag", "noindex"),
+        _ => builder,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{ACCEPT, CONTENT_TYPE};
+    use hyper::Method;
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn parse_range_variants() {
+        let cases = [
+            ("bytes=0-4", 10, Ok(Some(0..5))),
+            ("bytes=5-", 10, Ok(Some(5..10))),
+            ("bytes=-3", 10, Ok(Some(7..10))),
+            ("bytes=-30", 10, Ok(Some(0..10))),
+            ("bytes=8-20", 10, Ok(Some(8..10))),
+            ("bytes=0-18446744073709551615", 10, Ok(Some(0..10))),
+            ("bytes=10-", 10, Err(())),
+            ("bytes=10-12", 10, Err(())),
+            ("bytes=0-0", 0, Err(())),
+            ("bytes=-5", 0, Err(())),
+            ("bytes=5-2", 10, Ok(None)),
+            ("bytes=-0", 10, Ok(None)),
+            ("bytes=0-1,3-4", 10, Ok(None)),
+            ("items=0-1", 10, Ok(None)),
+            ("bytes=a-b", 10, Ok(None)),
+        ];
+
+        for (value, size, expected) in cases {
+            assert_eq!(
+                parse_range(value, size),
+                expected,
+                "{value} of {size} bytes"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn private_clips_are_hidden() {
+        let ip = new_ip();
+        let private = [("visibility", "private")];
+        let id = uploaded_id(upload(ip, "red", "team secret", &private).await).await;
+        let path = format!("/{id}");
+
+        for user in [Some("red"), Some("purple"), Some("admin")] {
+            let resp = get(ip, &path, user).await;
+            assert_eq!(resp.status(), 200, "{user:?}");
+            assert_eq!(text(resp).await, "team secret");
+        }
+        for user in [None, Some("blue"), Some("other")] {
+            assert_eq!(get(ip, &path, user).await.status(), 404, "{user:?}");
+        }
+
+        // Viewing private clips needs the read-private scope, even for the author
+        let id = uploaded_id(upload(ip, "uploader", "own secret", &private).await).await;
+        assert_eq!(
+            get(ip, &format!("/{id}"), Some("uploader")).await.status(),
+            404
+        );
+        assert_eq!(
+            get(ip, &format!("/{id}"), Some("admin")).await.status(),
+            200
+        );
+    }
+
+    #[tokio::test]
+    async fn restricted_clips_are_deduplicated_per_author() {
+        let ip = new_ip();
+        let public = uploaded_id(upload(ip, "red", "same data", &[]).await).await;
+        assert_eq!(
+            public,
+            uploaded_id(upload(ip, "blue", "same data", &[]).await).await
+        );
+
+        for visibility in ["private", "unlisted"] {
+            let header = [("visibility", visibility)];
+            let red = uploaded_id(upload(ip, "red", "same data", &header).await).await;
+            let blue = uploaded_id(upload(ip, "blue", "same data", &header).await).await;
+
+            assert_ne!(red, blue);
+            assert_ne!(red, public);
+            assert_eq!(
+                red,
+                uploaded_id(upload(ip, "red", "same data", &header).await).await
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn password_protected_clips() {
+        let ip = new_ip();
+        let password = [("clip-password", "hunter2")];
+        let id = uploaded_id(upload(ip, "protector", "protected", &password).await).await;
+        let path = format!("/{id}");
+        let get_with = |headers: &[(&str, &str)]| {
+            let mut req = request(Method::GET, &path, None);
+            for (name, value) in headers {
+                req = req.header(*name, *value);
+            }
+            send(ip, req.body(Full::default()).unwrap())
+        };
+        let post_form = |form: &'static str| {
+            let req = request(Method::POST, &path, None)
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Full::new(Bytes::from(form)))
+                .unwrap();
+            send(ip, req)
+        };
+
+        let resp = get_with(&[]).await;
+        assert_eq!(resp.status(), ErrorCode::PasswordRequired.status());
+        assert!(text(resp).await.contains("clip-password header"));
+        let resp = get_with(&[(ACCEPT.as_str(), "text/html")]).await;
+        assert!(text(resp).await.contains("<form method=\"post\""));
+
+        // Correct passwords don't count against the limit of 2 attempts
+        let resp = get_with(&password).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(text(resp).await, "protected");
+        let resp = post_form("password=hunter2").await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(text(resp).await, "protected");
+
+        let resp = get_with(&[("clip-password", "wrong")]).await;
+        assert_eq!(resp.status(), ErrorCode::WrongPassword.status());
+        assert!(text(resp).await.contains("Wrong password"));
+        assert_eq!(post_form("password=wrong").await.status(), 401);
+        assert_eq!(get_with(&password).await.status(), 429);
+
+        // Other clients still get in
+        let req = request(Method::GET, &path, None)
+            .header("clip-password", "hunter2")
+            .body(Full::default())
+            .unwrap();
+        assert_eq!(send(new_ip(), req).await.status(), 200);
+    }
 }
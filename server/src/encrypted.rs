@@ -0,0 +1,154 @@
+use base64::{engine::general_purpose, Engine as _};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE, REFERRER_POLICY};
+use hyper::Response;
+
+/// The page may run its inline script, but can't send the decrypted clip anywhere
+const PAGE_CSP: &str = "default-src 'none'; script-src 'unsafe-inline'; \
+    style-src 'unsafe-inline'; img-src blob:; media-src blob:; connect-src 'none'";
+
+/// Added after forms that post back to a clip, so the key in the url fragment survives the submission
+pub const KEEP_KEY_SCRIPT: &str =
+    "<script>for (const form of document.forms) form.action += location.hash;</script>";
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Encrypted clip</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+pre { white-space: pre-wrap; word-break: break-all; }
+img { max-width: 100%; }
+</style>
+</head>
+<body>
+<div id="out">Decrypting...</div>
+<script id="payload" type="application/octet-stream">{payload}</script>
+<script>
+const out = document.getElementById("out");
+const decode = (s) => Uint8Array.from(atob(s.replace(/-/g, "+").replace(/_/g, "/")), (c) => c.charCodeAt(0));
+
+function show(type, data) {
+    out.textContent = "";
+    if (type === "text/uri-list") {
+        const url = new TextDecoder().decode(data).split("\n")[0].trim();
+        if (["http:", "https:"].includes(new URL(url).protocol)) {
+            location.replace(url);
+            return;
+        }
+    }
+    if (type.startsWith("text/") || type === "application/json") {
+        const pre = document.createElement("pre");
+        pre.textContent = new TextDecoder().decode(data);
+        out.append(pre);
+        return;
+    }
+    const url = URL.createObjectURL(new Blob([data], { type }));
+    if (type.startsWith("image/")) {
+        const img = document.createElement("img");
+        img.src = url;
+        out.append(img);
+        return;
+    }
+    const link = document.createElement("a");
+    link.href = url;
+    link.download = "clip";
+    link.textContent = "Download (" + type + ")";
+    out.append(link);
+}
+
+async function decrypt() {
+    const key = location.hash.slice(1);
+    if (!key) {
+        out.textContent = "This clip is encrypted and the link is missing its key.";
+        return;
+    }
+    if (!window.crypto || !crypto.subtle) {
+        out.textContent = "Your browser can't decrypt this clip, it has to be opened over https.";
+        return;
+    }
+    try {
+        const payload = decode(document.getElementById("payload").textContent.trim());
+        const cryptoKey = await crypto.subtle.importKey("raw", decode(key), "AES-GCM", false, ["decrypt"]);
+        const plain = new Uint8Array(
+            await crypto.subtle.decrypt({ name: "AES-GCM", iv: payload.slice(0, 12) }, cryptoKey, payload.slice(12))
+        );
+        const split = plain.indexOf(10);
+        show(new TextDecoder().decode(plain.slice(0, split)), plain.slice(split + 1));
+    } catch (e) {
+        out.textContent = "Unable to decrypt this clip, the key in the link is wrong.";
+    }
+}
+
+decrypt();
+</script>
+</body>
+</html>
+"#;
+
+/// Page decrypting an end-to-end encrypted clip in the browser
+///
+/// The key is part of the url fragment, which browsers never send to the server
+pub fn decrypt_page(data: &[u8]) -> Response<Full<Bytes>> {
+    // Base64 can't contain any characters that would end the script element
+    let page = PAGE.replace("{payload}", &general_purpose::STANDARD.encode(data));
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(CACHE_CONTROL, "no-store")
+        .header(CONTENT_SECURITY_POLICY, PAGE_CSP)
+        .header(REFERRER_POLICY, "no-referrer")
+        .header("x-robots-tag", "noindex")
+        .body(Full::new(Bytes::from(page)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use hyper::header::ACCEPT;
+    use hyper::Request;
+    use shared::{Metadata, Visibility};
+
+    use super::KEEP_KEY_SCRIPT;
+    use crate::password::password_required;
+    use crate::views::reveal_page;
+
+    async fn page(resp: hyper::Response<http_body_util::Full<hyper::body::Bytes>>) -> String {
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn forms_keep_the_key_of_encrypted_clips() {
+        let req = Request::builder()
+            .header(ACCEPT, "text/html")
+            .body(())
+            .unwrap();
+        let metadata = Metadata {
+            version: shared::METADATA_VERSION,
+            created_at: 0,
+            expires_at: None,
+            author: "alice".to_owned(),
+            content_type: "text/plain".to_owned(),
+            visibility: Visibility::Public,
+            password_hash: Some("hash".to_owned()),
+            max_views: Some(1),
+            views: 0,
+            encrypted: true,
+        };
+
+        for page in [
+            page(password_required(&req, "abc", false)).await,
+            page(reveal_page("abc", &metadata)).await,
+        ] {
+            let form = page.find(r#"<form method="post" action="/abc">"#).unwrap();
+            let script = page.find(KEEP_KEY_SCRIPT).unwrap();
+            assert!(form < script);
+        }
+    }
+}
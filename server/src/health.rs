@@ -0,0 +1,110 @@
+use std::sync::OnceLock;
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::Response;
+
+use crate::db;
+use crate::storage::{storage, StorageResult};
+use crate::tokens::tokens_status;
+
+/// Default amount of free space required for `/readyz`, 100 MiB
+const DEFAULT_MIN_FREE_SPACE: u64 = 100 * 1024 * 1024;
+
+static MIN_FREE_SPACE: OnceLock<u64> = OnceLock::new();
+
+/// Reads the free space required by `/readyz` from `MIN_FREE_SPACE`, in bytes
+pub fn init() -> Result<(), String> {
+    let min_free_space = match std::env::var("MIN_FREE_SPACE") {
+        Ok(v) => v
+            .parse()
+            .map_err(|_| format!("MIN_FREE_SPACE must be a number of bytes, found \"{v}\""))?,
+        Err(_) => DEFAULT_MIN_FREE_SPACE,
+    };
+    let _ = MIN_FREE_SPACE.set(min_free_space);
+
+    Ok(())
+}
+
+/// Liveness probe, answers as long as the server is able to handle requests
+pub async fn handle_healthz() -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
+    Ok(probe_response(200, "ok".to_owned()))
+}
+
+/// Readiness probe, checks that uploads can actually be served
+///
+/// The minimum free disk space can be configured in bytes
+/// through the `MIN_FREE_SPACE` environment variable
+pub async fn handle_readyz() -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
+    let mut failures = Vec::new();
+
+    if let Err(e) = tokens_status() {
+        failures.push(format!("tokens file not loaded: {e}"));
+    }
+
+    if let Err(e) = db::check().await {
+        failures.push(format!("database not available: {e}"));
+    }
+
+    if let Err(e) = check_storage_writable().await {
+        failures.push(format!("storage not writable: {e}"));
+    }
+
+    let min_free_space = *MIN_FREE_SPACE.get().unwrap_or(&DEFAULT_MIN_FREE_SPACE);
+    match storage().available_space() {
+        Some(Ok(available)) if available < min_free_space => failures.push(format!(
+            "free disk space below threshold ({available} < {min_free_space} bytes)"
+        )),
+        Some(Err(e)) => failures.push(format!("unable to query free disk space: {e}")),
+        _ => {}
+    }
+
+    if failures.is_empty() {
+        Ok(probe_response(200, "ready".to_owned()))
+    } else {
+        Ok(probe_response(503, failures.join("\n")))
+    }
+}
+
+/// Writes and removes a probe object
+async fn check_storage_writable() -> StorageResult<()> {
+    storage().put(".readyz", b"ok").await?;
+    storage().delete(".readyz").await
+}
+
+fn probe_response(status: u16, msg: String) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain")
+        // Probes should never be answered from a cache
+        .header(CACHE_CONTROL, "no-store")
+        .body(Full::new(Bytes::from(msg)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::CACHE_CONTROL;
+
+    use crate::storage::storage;
+    use crate::testing::*;
+
+    #[tokio::test]
+    async fn probes() {
+        let ip = new_ip();
+
+        let resp = get(ip, "/healthz", None).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()[CACHE_CONTROL], "no-store");
+        assert_eq!(text(resp).await, "ok");
+
+        let resp = get(ip, "/readyz", None).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()[CACHE_CONTROL], "no-store");
+        assert_eq!(text(resp).await, "ready");
+
+        // The probe object is removed again
+        assert_eq!(storage().head(".readyz").await.unwrap(), None);
+    }
+}
@@ -2,54 +2,178 @@ use std::convert::Infallible;
 use std::net::SocketAddr;
 
 use http_body_util::Full;
-use hyper::body::Bytes;
+use hyper::body::{Body, Bytes};
+use hyper::header::HeaderValue;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{Request, Response};
 use hyper_util::rt::TokioIo;
+use shared::api::{headers, routes, ErrorCode, API_VERSION};
 
 use hyper::Method;
 use tokio::net::TcpListener;
 
+mod admin;
+mod audit;
+mod clips;
+mod db;
+mod delete;
 mod download;
+mod encrypted;
+mod health;
+mod password;
+mod quota;
+mod ratelimit;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod tokens;
 mod upload;
 mod util;
+mod views;
 
+use clips::{handle_info, handle_list};
+use delete::handle_delete;
 use download::handle_download;
+use health::{handle_healthz, handle_readyz};
+use quota::handle_usage;
 use upload::handle_upload;
 
 use crate::util::*;
 
-async fn handle_req(
-    req: Request<hyper::body::Incoming>,
-) -> Result<Response<Full<Bytes>>, Infallible> {
-    Ok(
-        match match req.method() {
-            &Method::GET => handle_download(req).await,
-            &Method::POST => handle_upload(req).await,
-            _ => Ok(not_found()),
-        } {
+async fn handle_req<B>(
+    req: Request<B>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Infallible>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    let mut resp = if let Some(version) = unsupported_version(&req) {
+        bad_version(version)
+    } else {
+        match route(req, remote_addr).await {
             Ok(r) => r,
             Err(e) => internal_error(e),
-        },
-    )
+        }
+    };
+
+    resp.headers_mut().insert(
+        headers::API_VERSION,
+        HeaderValue::from(u16::from(API_VERSION)),
+    );
+
+    Ok(resp)
+}
+
+async fn route<B>(
+    req: Request<B>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, routes::HEALTHZ) => handle_healthz().await,
+        (&Method::GET, routes::READYZ) => handle_readyz().await,
+        (&Method::GET, routes::USAGE) => handle_usage(req, remote_addr).await,
+        (&Method::GET, routes::CLIPS) => handle_list(req, remote_addr).await,
+        (&Method::GET, path) if path.starts_with(&format!("{}/", routes::CLIPS)) => {
+            handle_info(req, remote_addr).await
+        }
+        (&Method::GET, _) => handle_download(req, remote_addr).await,
+        (&Method::POST, routes::UPLOAD) => handle_upload(req, remote_addr).await,
+        (&Method::POST, path) if clip_id(path).is_some() => handle_download(req, remote_addr).await,
+        (&Method::DELETE, _) => handle_delete(req, remote_addr).await,
+        _ => Ok(not_found()),
+    }
+}
+
+/// The API version requested by the client, if this server doesn't speak it
+///
+/// Requests without the version header are treated as the current version
+fn unsupported_version<B>(req: &Request<B>) -> Option<String> {
+    let version = req.headers().get(headers::API_VERSION)?;
+
+    match version.to_str().map(|v| v.trim().parse::<u8>()) {
+        Ok(Ok(API_VERSION)) => None,
+        _ => Some(String::from_utf8_lossy(version.as_bytes()).into_owned()),
+    }
+}
+
+fn bad_version(version: String) -> Response<Full<Bytes>> {
+    let msg =
+        format!("Unsupported API version {version}, this server speaks version {API_VERSION}");
+
+    error_response(ErrorCode::UnsupportedApiVersion)
+        .body(error_body(ErrorCode::UnsupportedApiVersion, &msg))
+        .unwrap()
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = admin::run(&args).await {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+
+        return Ok(());
+    }
+
     let port = std::env::var("PORT").unwrap_or("3000".to_owned());
     let port: u16 = port.parse()?;
     let addr = SocketAddr::from(([127, 0, 0, 1], port));
 
+    if let Err(e) = health::init() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = quota::init() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = ratelimit::init() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = audit::init().await {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = storage::init() {
+        eprintln!("Unable to set up storage: {e}");
+        std::process::exit(1);
+    }
+    if let Err(e) = db::init() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    match db::migrate().await {
+        Ok(0) => {}
+        Ok(count) => eprintln!("Moved the metadata of {count} clip(s) into the metadata store"),
+        Err(e) => {
+            eprintln!("Unable to migrate metadata into the metadata store: {e}");
+            std::process::exit(1);
+        }
+    }
+
     // Init authorized token list
-    upload::initialise_cells().await?;
+    if let Err(e) = tokens::load().await {
+        eprintln!("Unable to load authorized tokens: {e}");
+        std::process::exit(1);
+    }
+    tokens::spawn_reloader();
+    upload::initialise_cells();
 
     // We create a TcpListener and bind it to 127.0.0.1:3000
     let listener = TcpListener::bind(addr).await?;
 
     // We start a loop to continuously accept incoming connections
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, remote_addr) = listener.accept().await?;
 
         // Use an adapter to access something implementing `tokio::io` traits as if they implement
         // `hyper::rt` IO traits.
@@ -60,7 +184,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             // Finally, we bind the incoming connection to our `hello` service
             if let Err(err) = http1::Builder::new()
                 // `service_fn` converts our function in a `Service`
-                .serve_connection(io, service_fn(handle_req))
+                .serve_connection(io, service_fn(move |req| handle_req(req, remote_addr)))
                 .await
             {
                 println!("Error serving connection: {:?}", err);
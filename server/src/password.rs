@@ -0,0 +1,116 @@
+use argon2::password_hash::phc::PasswordHash;
+use argon2::password_hash::{PasswordHasher, PasswordVerifier};
+use argon2::Argon2;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::http::response::Builder;
+use hyper::{Request, Response};
+use shared::api::ErrorCode;
+
+use crate::encrypted::KEEP_KEY_SCRIPT;
+use crate::util::{error_body, error_response};
+
+/// Hashes a clip password with argon2 and a random salt into a PHC string
+///
+/// Argon2 is slow, so both hashing and verifying run on a blocking thread
+pub async fn hash_password(password: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let password = password.to_owned();
+    let hash = tokio::task::spawn_blocking(move || {
+        Argon2::default()
+            .hash_password(password.as_bytes())
+            .map(|hash| hash.to_string())
+            .map_err(|e| format!("Unable to hash password: {e}"))
+    })
+    .await??;
+
+    Ok(hash)
+}
+
+pub async fn verify_password(password: &str, password_hash: &str) -> bool {
+    let password = password.to_owned();
+    let password_hash = password_hash.to_owned();
+
+    tokio::task::spawn_blocking(move || match PasswordHash::new(&password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Whether the request was sent by a browser that can display the prompt page
+pub fn wants_html<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"))
+}
+
+/// Response for a password protected clip requested without a valid password
+///
+/// Browsers get a page with a form that posts the password back to the clip,
+/// API clients are told to send the `clip-password` header
+pub fn password_required<B>(req: &Request<B>, id: &str, wrong: bool) -> Response<Full<Bytes>> {
+    let code = if wrong {
+        ErrorCode::WrongPassword
+    } else {
+        ErrorCode::PasswordRequired
+    };
+    let challenge = |builder: Builder| {
+        builder
+            .header(WWW_AUTHENTICATE, "Clip-Password realm=\"short_clip\"")
+            .header(CACHE_CONTROL, "no-store")
+    };
+
+    if !wants_html(req) {
+        let msg = if wrong {
+            "Wrong password"
+        } else {
+            "Password required, send it in the clip-password header"
+        };
+
+        return challenge(error_response(code))
+            .body(error_body(code, msg))
+            .unwrap();
+    }
+
+    let error = if wrong {
+        "<p class=\"error\">Wrong password</p>"
+    } else {
+        ""
+    };
+    // `id` only consists of url safe base64 characters, so it can be embedded as is
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Password required</title>
+<style>
+body {{ font-family: sans-serif; display: flex; justify-content: center; margin-top: 20vh; }}
+.error {{ color: #c00; }}
+</style>
+</head>
+<body>
+<form method="post" action="/{id}">
+<p>This clip is protected by a password.</p>
+{error}
+<input type="password" name="password" autofocus required>
+<button type="submit">Open</button>
+</form>
+{KEEP_KEY_SCRIPT}
+</body>
+</html>
+"#
+    );
+
+    challenge(Response::builder().status(code.status()))
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Full::new(Bytes::from(page)))
+        .unwrap()
+}
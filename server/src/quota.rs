@@ -0,0 +1,286 @@
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Request, Response};
+use serde::Serialize;
+use shared::api::{AllUsageResponse, Usage, UsageResponse};
+
+use crate::db;
+use crate::tokens::{authenticate, Scope};
+use crate::util::*;
+
+static DEFAULT_QUOTA: OnceLock<Quota> = OnceLock::new();
+
+/// Storage limits of a single user, `None` means unlimited
+#[derive(Serialize, Clone, Copy, Default, Debug)]
+pub struct Quota {
+    /// Total amount of bytes stored
+    pub max_bytes: Option<u64>,
+    /// Number of stored clips
+    pub max_clips: Option<u64>,
+    /// Size of a single upload in bytes
+    pub max_upload: Option<u64>,
+}
+
+impl Quota {
+    /// Parses a `key=value` option from the `.authorized_tokens` file
+    ///
+    /// Returns `Ok(false)` if the key is not a quota option
+    pub fn parse_option(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        let slot = match key {
+            "max_bytes" => &mut self.max_bytes,
+            "max_clips" => &mut self.max_clips,
+            "max_upload" => &mut self.max_upload,
+            _ => return Ok(false),
+        };
+
+        *slot = Some(parse_size(value).ok_or(format!("Invalid value for {key}: {value}"))?);
+
+        Ok(true)
+    }
+
+    /// Fills all unset limits with the shared defaults
+    pub fn or_default(&self) -> Quota {
+        let default = default_quota();
+
+        Quota {
+            max_bytes: self.max_bytes.or(default.max_bytes),
+            max_clips: self.max_clips.or(default.max_clips),
+            max_upload: self.max_upload.or(default.max_upload),
+        }
+    }
+
+    /// Checks whether a new clip of `size` bytes fits into this quota
+    ///
+    /// Returns a human readable error message if it doesn't. The usage is only accurate
+    /// while `handle_upload` holds its process global `UPLOAD_LOCK`
+    pub fn check(&self, usage: &Usage, size: u64) -> Result<(), String> {
+        if let Some(max_upload) = self.max_upload {
+            if size > max_upload {
+                return Err(format!(
+                    "Quota exceeded: upload of {size} bytes is larger than the limit of {max_upload} bytes"
+                ));
+            }
+        }
+
+        if let Some(max_clips) = self.max_clips {
+            if usage
+                .clips
+                .checked_add(1)
+                .is_none_or(|clips| clips > max_clips)
+            {
+                return Err(format!(
+                    "Quota exceeded: the limit of {max_clips} clips has been reached"
+                ));
+            }
+        }
+
+        if let Some(max_bytes) = self.max_bytes {
+            if usage
+                .bytes
+                .checked_add(size)
+                .is_none_or(|bytes| bytes > max_bytes)
+            {
+                return Err(format!(
+                    "Quota exceeded: {} of {max_bytes} bytes in use, upload of {size} bytes doesn't fit",
+                    usage.bytes
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the shared default quota from the
+/// `QUOTA_MAX_BYTES`, `QUOTA_MAX_CLIPS` and `QUOTA_MAX_UPLOAD` environment variables
+pub fn init() -> Result<(), String> {
+    let var = |name: &str| match std::env::var(name) {
+        Ok(v) => parse_size(&v)
+            .map(Some)
+            .ok_or(format!("Invalid value for {name}: {v}")),
+        Err(_) => Ok(None),
+    };
+
+    let quota = Quota {
+        max_bytes: var("QUOTA_MAX_BYTES")?,
+        max_clips: var("QUOTA_MAX_CLIPS")?,
+        max_upload: var("QUOTA_MAX_UPLOAD")?,
+    };
+    let _ = DEFAULT_QUOTA.set(quota);
+
+    Ok(())
+}
+
+/// The shared default quota, `init` has to be called first
+pub fn default_quota() -> &'static Quota {
+    DEFAULT_QUOTA.get().expect("Quota not initialised")
+}
+
+/// Sums up the stored clips of every user
+///
+/// Expired clips that haven't been deleted yet are not counted
+pub async fn all_usage() -> Result<AllUsageResponse, Box<dyn std::error::Error>> {
+    Ok(db::usage(None)
+        .await?
+        .into_iter()
+        .map(|(author, bytes, clips)| (author, Usage { bytes, clips }))
+        .collect())
+}
+
+/// Sums up the stored clips of a single user
+pub async fn user_usage(username: &str) -> Result<Usage, Box<dyn std::error::Error>> {
+    Ok(db::usage(Some(username))
+        .await?
+        .into_iter()
+        .next()
+        .map(|(_, bytes, clips)| Usage { bytes, clips })
+        .unwrap_or_default())
+}
+
+/// Reports the current consumption and limits of the authenticated user
+pub async fn handle_usage<B>(
+    req: Request<B>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
+    let entry = match authenticate(&req, client_ip(&req, remote_addr)).await {
+        Ok(e) => e,
+        Err(e) => return Ok(unauthorized(e)),
+    };
+
+    // Admins can inspect the consumption of every user with `/usage?all`
+    let body = if req.uri().query() == Some("all") {
+        if !entry.has_scope(Scope::Admin) {
+            return Ok(forbidden("Only admins can view the usage of all users"));
+        }
+
+        serde_json::to_string(&all_usage().await?)?
+    } else {
+        let usage = user_usage(&entry.username).await?;
+        let quota = entry.quota.or_default();
+
+        serde_json::to_string(&UsageResponse {
+            user: entry.username.clone(),
+            usage,
+            max_bytes: quota.max_bytes,
+            max_clips: quota.max_clips,
+            max_upload: quota.max_upload,
+        })?
+    };
+
+    let resp = Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)))?;
+
+    Ok(resp)
+}
+
+/// Parses a byte size with an optional binary `K`, `M` or `G` suffix
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last()? {
+        (i, 'K' | 'k') => (&s[..i], 1 << 10),
+        (i, 'M' | 'm') => (&s[..i], 1 << 20),
+        (i, 'G' | 'g') => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use shared::api::{AllUsageResponse, UsageResponse};
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size(" 2K "), Some(2048));
+        assert_eq!(parse_size("3m"), Some(3 << 20));
+        assert_eq!(parse_size("1G"), Some(1 << 30));
+        assert_eq!(parse_size("K"), None);
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_size("99999999999999999999G"), None);
+        assert_eq!(parse_size(&format!("{}G", u64::MAX >> 20)), None);
+    }
+
+    #[test]
+    fn check_limits() {
+        let quota = Quota {
+            max_bytes: Some(100),
+            max_clips: Some(3),
+            max_upload: Some(50),
+        };
+        let usage = |bytes, clips| Usage { bytes, clips };
+
+        assert!(quota.check(&usage(0, 0), 50).is_ok());
+        assert!(quota.check(&usage(0, 0), 51).is_err());
+        assert!(quota.check(&usage(50, 2), 50).is_ok());
+        assert!(quota.check(&usage(51, 2), 50).is_err());
+        assert!(quota.check(&usage(0, 3), 1).is_err());
+
+        // Usage beyond any sane value is rejected instead of wrapping around
+        let quota = Quota {
+            max_bytes: Some(u64::MAX),
+            max_clips: Some(u64::MAX),
+            max_upload: None,
+        };
+        assert!(quota.check(&usage(u64::MAX, 0), 1).is_err());
+        assert!(quota.check(&usage(0, u64::MAX), 1).is_err());
+        assert!(Quota::default()
+            .check(&usage(u64::MAX, u64::MAX), u64::MAX)
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn uploads_are_limited() {
+        let ip = new_ip();
+
+        // max_upload is checked against the Content-Length and the actual body
+        let resp = upload(ip, "quota", "too large", &[]).await;
+        assert_eq!(resp.status(), 413);
+        let resp = upload(ip, "quota", "too large", &[("content-length", "9")]).await;
+        assert_eq!(resp.status(), 413);
+
+        uploaded_id(upload(ip, "quota", "first", &[]).await).await;
+        // The same data doesn't count twice
+        uploaded_id(upload(ip, "quota", "first", &[]).await).await;
+
+        // max_bytes, 5 of 12 bytes are in use
+        let resp = upload(ip, "quota", "8 bytes!", &[]).await;
+        assert_eq!(resp.status(), 413);
+        assert!(text(resp).await.contains("5 of 12 bytes in use"));
+
+        uploaded_id(upload(ip, "quota", "second", &[]).await).await;
+
+        // max_clips
+        let resp = upload(ip, "quota", "3", &[]).await;
+        assert_eq!(resp.status(), 413);
+        assert!(text(resp).await.contains("limit of 2 clips"));
+
+        let resp = get(ip, "/usage", Some("quota")).await;
+        assert_eq!(resp.status(), 200);
+        let usage: UsageResponse = serde_json::from_str(&text(resp).await).unwrap();
+        assert_eq!(usage.user, "quota");
+        assert_eq!((usage.usage.bytes, usage.usage.clips), (11, 2));
+        assert_eq!(usage.max_bytes, Some(12));
+        assert_eq!(usage.max_clips, Some(2));
+        assert_eq!(usage.max_upload, Some(8));
+
+        // Only admins see the usage of everyone
+        let resp = get(ip, "/usage?all", Some("quota")).await;
+        assert_eq!(resp.status(), 403);
+        let resp = get(ip, "/usage?all", Some("admin")).await;
+        assert_eq!(resp.status(), 200);
+        let all: AllUsageResponse = serde_json::from_str(&text(resp).await).unwrap();
+        assert_eq!(all["quota"].bytes, 11);
+
+        assert_eq!(get(ip, "/usage", None).await.status(), 401);
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+static UPLOAD_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+static DOWNLOAD_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+static PASSWORD_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+static AUTH_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+static NOT_FOUND_COST: OnceLock<f64> = OnceLock::new();
+
+/// Buckets are only cleaned up once the map grows beyond this size
+const CLEANUP_THRESHOLD: usize = 10_000;
+
+/// A set of token buckets, one per key
+pub struct RateLimiter {
+    /// Tokens refilled per second
+    rate: f64,
+    /// Maximum amount of tokens a bucket can hold
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `per_minute` requests per minute
+    /// with bursts of up to `burst` requests
+    ///
+    /// A rate of `0` disables the limiter
+    pub fn new(per_minute: f64, burst: f64) -> Self {
+        Self {
+            rate: per_minute / 60.0,
+            burst: burst.max(1.0),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes `cost` tokens from the bucket of `key`
+    ///
+    /// Returns the time until enough tokens are available if the bucket is empty
+    pub fn check(&self, key: &str, cost: f64) -> Result<(), Duration> {
+        if self.rate <= 0.0 {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = self.refilled_bucket(&mut buckets, key);
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            let missing = cost - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / self.rate))
+        }
+    }
+
+    /// Returns `cost` tokens taken by `check` to the bucket of `key`
+    ///
+    /// Used to only count failed attempts while still reserving a token before each one
+    pub fn refund(&self, key: &str, cost: f64) {
+        if self.rate <= 0.0 {
+            return;
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = self.refilled_bucket(&mut buckets, key);
+
+        bucket.tokens = (bucket.tokens + cost).min(self.burst);
+    }
+
+    /// Takes `cost` tokens from the bucket of `key` even if it runs into debt
+    ///
+    /// Used to punish requests after the fact, e.g. downloads of unknown ids
+    pub fn penalize(&self, key: &str, cost: f64) {
+        if self.rate <= 0.0 {
+            return;
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = self.refilled_bucket(&mut buckets, key);
+
+        // Limit the debt so a bucket refills within a reasonable time
+        bucket.tokens = (bucket.tokens - cost).max(-self.burst);
+    }
+
+    fn refilled_bucket<'a>(
+        &self,
+        buckets: &'a mut HashMap<String, Bucket>,
+        key: &str,
+    ) -> &'a mut Bucket {
+        let now = Instant::now();
+
+        if buckets.len() > CLEANUP_THRESHOLD {
+            // Buckets that refilled completely behave exactly like new ones
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.last_refill).as_secs_f64() * self.rate < self.burst
+            });
+        }
+
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last_refill = now;
+
+        bucket
+    }
+}
+
+/// Sets up the limiters from their environment variables
+pub fn init() -> Result<(), String> {
+    let limiter = |rate, default_rate, burst, default_burst| -> Result<RateLimiter, String> {
+        Ok(RateLimiter::new(
+            env_f64(rate, default_rate)?,
+            env_f64(burst, default_burst)?,
+        ))
+    };
+
+    let _ = UPLOAD_LIMITER.set(limiter("UPLOAD_RATE", 30.0, "UPLOAD_BURST", 10.0)?);
+    let _ = DOWNLOAD_LIMITER.set(limiter("DOWNLOAD_RATE", 120.0, "DOWNLOAD_BURST", 60.0)?);
+    let _ = PASSWORD_LIMITER.set(limiter("PASSWORD_RATE", 5.0, "PASSWORD_BURST", 5.0)?);
+    let _ = AUTH_LIMITER.set(limiter("AUTH_RATE", 10.0, "AUTH_BURST", 10.0)?);
+    let _ = NOT_FOUND_COST.set(env_f64("DOWNLOAD_NOT_FOUND_COST", 5.0)?);
+
+    Ok(())
+}
+
+/// Limiter for uploads, keyed by username so all tokens of a user share it
+///
+/// Configured through `UPLOAD_RATE` (requests per minute, default 30)
+/// and `UPLOAD_BURST` (default 10)
+pub fn upload_limiter() -> &'static RateLimiter {
+    UPLOAD_LIMITER.get().expect("Rate limits not initialised")
+}
+
+/// Limiter for downloads, keyed by client IP
+///
+/// Configured through `DOWNLOAD_RATE` (requests per minute, default 120)
+/// and `DOWNLOAD_BURST` (default 60)
+pub fn download_limiter() -> &'static RateLimiter {
+    DOWNLOAD_LIMITER.get().expect("Rate limits not initialised")
+}
+
+/// Limiter for wrong passwords of protected clips, keyed by client IP and clip id
+///
+/// Configured through `PASSWORD_RATE` (attempts per minute, default 5)
+/// and `PASSWORD_BURST` (default 5)
+pub fn password_limiter() -> &'static RateLimiter {
+    PASSWORD_LIMITER.get().expect("Rate limits not initialised")
+}
+
+/// Limiter for unknown tokens, keyed by client IP
+///
+/// Configured through `AUTH_RATE` (attempts per minute, default 10)
+/// and `AUTH_BURST` (default 10)
+pub fn auth_limiter() -> &'static RateLimiter {
+    AUTH_LIMITER.get().expect("Rate limits not initialised")
+}
+
+/// Additional cost of a download that ended in a 404, set through `DOWNLOAD_NOT_FOUND_COST`
+///
+/// Slows down guessing of ids, defaults to 5
+pub fn not_found_cost() -> f64 {
+    *NOT_FOUND_COST.get().expect("Rate limits not initialised")
+}
+
+fn env_f64(name: &str, default: f64) -> Result<f64, String> {
+    match std::env::var(name) {
+        Ok(v) => match v.parse::<f64>() {
+            Ok(n) if n.is_finite() && n >= 0.0 => Ok(n),
+            _ => Err(format!(
+                "Invalid value for {name}: {v}, expected a non-negative number"
+            )),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::RETRY_AFTER;
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn buckets() {
+        let limiter = RateLimiter::new(60.0, 2.0);
+
+        assert!(limiter.check("a", 1.0).is_ok());
+        assert!(limiter.check("a", 1.0).is_ok());
+        let retry_after = limiter.check("a", 1.0).unwrap_err();
+        assert!(retry_after > Duration::from_millis(900) && retry_after <= Duration::from_secs(1));
+
+        // Every key has its own bucket
+        assert!(limiter.check("b", 2.0).is_ok());
+        assert!(limiter.check("b", 3.0).is_err());
+    }
+
+    #[test]
+    fn penalties() {
+        let limiter = RateLimiter::new(60.0, 3.0);
+
+        limiter.penalize("a", 2.0);
+        assert!(limiter.check("a", 1.0).is_ok());
+        limiter.penalize("a", 2.0);
+        // The bucket is in debt now
+        let retry_after = limiter.check("a", 1.0).unwrap_err();
+        assert!(retry_after > Duration::from_millis(2900) && retry_after <= Duration::from_secs(3));
+
+        // The debt is capped at the burst size
+        limiter.penalize("a", 100.0);
+        let retry_after = limiter.check("a", 1.0).unwrap_err();
+        assert!(retry_after > Duration::from_millis(3900) && retry_after <= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn refunds() {
+        let limiter = RateLimiter::new(60.0, 2.0);
+
+        limiter.check("a", 2.0).unwrap();
+        limiter.refund("a", 1.0);
+        assert!(limiter.check("a", 1.0).is_ok());
+        assert!(limiter.check("a", 1.0).is_err());
+
+        // Refunds never fill a bucket beyond the burst size
+        limiter.refund("b", 5.0);
+        assert!(limiter.check("b", 3.0).is_err());
+    }
+
+    #[test]
+    fn zero_rate_disables_the_limiter() {
+        let limiter = RateLimiter::new(0.0, 1.0);
+
+        limiter.penalize("a", 100.0);
+        for _ in 0..10 {
+            assert!(limiter.check("a", 1.0).is_ok());
+        }
+    }
+
+    #[test]
+    fn env_values() {
+        std::env::set_var("SHORT_CLIP_TEST_RATE", "2.5");
+        assert_eq!(env_f64("SHORT_CLIP_TEST_RATE", 1.0), Ok(2.5));
+        std::env::set_var("SHORT_CLIP_TEST_RATE", "-1");
+        assert!(env_f64("SHORT_CLIP_TEST_RATE", 1.0).is_err());
+        std::env::set_var("SHORT_CLIP_TEST_RATE", "inf");
+        assert!(env_f64("SHORT_CLIP_TEST_RATE", 1.0).is_err());
+        std::env::remove_var("SHORT_CLIP_TEST_RATE");
+        assert_eq!(env_f64("SHORT_CLIP_TEST_RATE", 1.0), Ok(1.0));
+    }
+
+    #[tokio::test]
+    async fn uploads_are_limited_per_user() {
+        // The limit follows the user, not the client IP or the token
+        for user in ["limited", "limited-second"].repeat(5) {
+            uploaded_id(upload(new_ip(), user, "data", &[]).await).await;
+        }
+
+        let resp = upload(new_ip(), "limited", "data", &[]).await;
+        assert_eq!(resp.status(), 429);
+        assert!(resp.headers().contains_key(RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn unknown_ids_cost_extra() {
+        let ip = new_ip();
+
+        // Every 404 costs 1 + 5 of the 60 downloads in a burst
+        for _ in 0..10 {
+            assert_eq!(get(ip, "/unknown", None).await.status(), 404);
+        }
+        let resp = get(ip, "/unknown", None).await;
+        assert_eq!(resp.status(), 429);
+        assert!(resp.headers().contains_key(RETRY_AFTER));
+
+        // Other clients aren't affected
+        assert_eq!(get(new_ip(), "/unknown", None).await.status(), 404);
+    }
+}
@@ -0,0 +1,423 @@
+use std::io::{Error, ErrorKind, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use tokio::sync::RwLock;
+
+use super::{Storage, StorageResult};
+
+/// Environment variable pointing to the key file, encryption at rest is disabled without it
+pub const KEYS_VAR: &str = "ENCRYPTION_KEYS";
+
+/// Prefix of every encrypted object
+const MAGIC: &[u8; 4] = b"SCE1";
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+
+static KEY_RING: OnceLock<Option<KeyRing>> = OnceLock::new();
+
+/// Keys from the key file, the last one is used to encrypt new files
+///
+/// Every line of the key file has the form `<key id> <base64 key>`,
+/// older keys are kept around to decrypt files written before a rotation
+pub struct KeyRing {
+    keys: Vec<(String, XChaCha20Poly1305)>,
+}
+
+impl KeyRing {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut keys = Vec::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (id, key) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("Line {}: expected \"<key id> <key>\"", i + 1))?;
+            let key = general_purpose::STANDARD
+                .decode(key.trim())
+                .map_err(|e| format!("Line {}: invalid key: {e}", i + 1))?;
+            let cipher = XChaCha20Poly1305::new_from_slice(&key)
+                .map_err(|_| format!("Line {}: keys must be 32 bytes", i + 1))?;
+
+            if id.len() > u8::MAX as usize || keys.iter().any(|(k, _)| k == id) {
+                return Err(format!("Line {}: invalid or duplicate key id {id}", i + 1));
+            }
+            keys.push((id.to_owned(), cipher));
+        }
+
+        if keys.is_empty() {
+            return Err("Key file contains no keys".to_owned());
+        }
+
+        Ok(Self { keys })
+    }
+
+    fn active(&self) -> &(String, XChaCha20Poly1305) {
+        self.keys.last().unwrap()
+    }
+
+    /// Encrypts `data` with the active key, `name` is authenticated so objects can't be swapped
+    fn seal(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let (id, cipher) = self.active();
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        getrandom::fill(&mut nonce).map_err(Error::other)?;
+        let ciphertext = cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: data,
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| Error::other("Unable to encrypt file"))?;
+
+        let mut sealed =
+            Vec::with_capacity(MAGIC.len() + 1 + id.len() + NONCE_SIZE + ciphertext.len());
+        sealed.extend_from_slice(MAGIC);
+        sealed.push(id.len() as u8);
+        sealed.extend_from_slice(id.as_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        Ok(sealed)
+    }
+
+    /// Decrypts a file written by `seal`, also returning whether it used an old key
+    fn open(&self, name: &str, sealed: &[u8]) -> Result<(Vec<u8>, bool), Error> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, format!("{name}: {msg}"));
+
+        let rest = &sealed[MAGIC.len()..];
+        let id_len = *rest.first().ok_or_else(|| invalid("truncated header"))? as usize;
+        if rest.len() < 1 + id_len + NONCE_SIZE {
+            return Err(invalid("truncated header"));
+        }
+        let (id, rest) = rest[1..].split_at(id_len);
+        let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+
+        let (index, (_, cipher)) = self
+            .keys
+            .iter()
+            .enumerate()
+            .find(|(_, (k, _))| k.as_bytes() == id)
+            .ok_or_else(|| invalid(&format!("unknown key {}", String::from_utf8_lossy(id))))?;
+        let data = cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid("decryption failed"))?;
+
+        Ok((data, index != self.keys.len() - 1))
+    }
+}
+
+/// Reads the key file set through `ENCRYPTION_KEYS`, if any
+pub fn load_key_ring() -> StorageResult<Option<&'static KeyRing>> {
+    if let Some(ring) = KEY_RING.get() {
+        return Ok(ring.as_ref());
+    }
+    let Ok(path) = std::env::var(KEYS_VAR) else {
+        return Ok(KEY_RING.get_or_init(|| None).as_ref());
+    };
+
+    let s = std::fs::read_to_string(&path)
+        .map_err(|e| Error::new(e.kind(), format!("Unable to read key file {path}: {e}")))?;
+    let ring = KeyRing::parse(&s).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid key file {path}: {e}"),
+        )
+    })?;
+
+    Ok(KEY_RING.get_or_init(|| Some(ring)).as_ref())
+}
+
+/// Encrypts a value stored outside of the storage backend, e.g. in the database
+///
+/// Without a key file the value is returned as is
+pub fn seal_value(name: &str, data: &[u8]) -> StorageResult<Vec<u8>> {
+    match load_key_ring()? {
+        Some(keys) => keys.seal(name, data),
+        None => Ok(data.to_vec()),
+    }
+}
+
+/// Decrypts a value encrypted by `seal_value`, plaintext is returned as is
+pub fn open_value(name: &str, data: &[u8]) -> StorageResult<Vec<u8>> {
+    if !data.starts_with(MAGIC) {
+        return Ok(data.to_vec());
+    }
+
+    match load_key_ring()? {
+        Some(keys) => Ok(keys.open(name, data)?.0),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{name} is encrypted but {KEYS_VAR} is not set"),
+        )),
+    }
+}
+
+/// Encrypts every object of another backend, the key of an object is authenticated too
+///
+/// Objects stored in plaintext or with an older key are still readable
+/// and are re-encrypted with the active key when they are read
+pub struct EncryptedStorage {
+    inner: Box<dyn Storage>,
+    keys: &'static KeyRing,
+    /// Shared by puts and deletes, the re-encryption in `get` takes it exclusively
+    /// so it can't bring back a deleted object or undo a concurrent put
+    writes: RwLock<()>,
+}
+
+impl EncryptedStorage {
+    pub fn new(inner: Box<dyn Storage>, keys: &'static KeyRing) -> Self {
+        Self {
+            inner,
+            keys,
+            writes: RwLock::new(()),
+        }
+    }
+
+    /// Decrypts an object, returning whether it should be rewritten with the active key
+    fn decrypt(&self, key: &str, raw: Vec<u8>) -> StorageResult<(Vec<u8>, bool)> {
+        if !raw.starts_with(MAGIC) {
+            // Plaintext from before encryption was enabled
+            return Ok((raw, true));
+        }
+
+        self.keys.open(key, &raw)
+    }
+}
+
+#[async_trait]
+impl Storage for EncryptedStorage {
+    async fn put(&self, key: &str, data: &[u8]) -> StorageResult<()> {
+        let sealed = self.keys.seal(key, data)?;
+
+        let _guard = self.writes.read().await;
+        self.inner.put(key, &sealed).await
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> StorageResult<Option<Vec<u8>>> {
+        let Some(raw) = self.inner.get(key, None).await? else {
+            return Ok(None);
+        };
+        let (data, stale) = self.decrypt(key, raw)?;
+
+        // Skip the migration while objects are written, it is retried on the next read
+        if stale {
+            if let Ok(_guard) = self.writes.try_write() {
+                // Another request might have changed or deleted the object in the meantime
+                if let Some(raw) = self.inner.get(key, None).await? {
+                    let (data, stale) = self.decrypt(key, raw)?;
+                    if stale {
+                        self.inner.put(key, &self.keys.seal(key, &data)?).await?;
+                    }
+                }
+            }
+        }
+
+        // The whole object has to be decrypted to authenticate it
+        Ok(Some(match range {
+            Some(range) => data[range.start as usize..range.end as usize].to_vec(),
+            None => data,
+        }))
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<u64>> {
+        let Some(size) = self.inner.head(key).await? else {
+            return Ok(None);
+        };
+
+        // The header contains the length of the key id, the rest of the overhead is fixed
+        let header = self
+            .inner
+            .get(key, Some(0..(MAGIC.len() as u64 + 1).min(size)))
+            .await?;
+        match header {
+            Some(h) if h.starts_with(MAGIC) && h.len() > MAGIC.len() => {
+                let overhead = MAGIC.len() + 1 + h[MAGIC.len()] as usize + NONCE_SIZE + TAG_SIZE;
+                Ok(Some(size.saturating_sub(overhead as u64)))
+            }
+            Some(_) => Ok(Some(size)),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        let _guard = self.writes.read().await;
+        self.inner.delete(key).await
+    }
+
+    async fn list(&self) -> StorageResult<Vec<String>> {
+        self.inner.list().await
+    }
+
+    fn available_space(&self) -> Option<std::io::Result<u64>> {
+        self.inner.available_space()
+    }
+}
+
+/// Appends a new random key to the key file, making it the active key
+///
+/// The file is created with permissions only allowing the owner to read it
+pub fn generate_key(path: &Path) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut id = [0u8; 4];
+    let mut key = [0u8; 32];
+    getrandom::fill(&mut id)?;
+    getrandom::fill(&mut key)?;
+    let id: String = id.iter().map(|b| format!("{b:02x}")).collect();
+
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    writeln!(file, "{id} {}", general_purpose::STANDARD.encode(key))?;
+    file.sync_all()?;
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::fs::tests::TempDir;
+    use crate::storage::fs::FsStorage;
+    use crate::storage::tests::round_trip;
+
+    fn key_ring(ids: &[&str]) -> &'static KeyRing {
+        let lines: Vec<String> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| format!("{id} {}", general_purpose::STANDARD.encode([i as u8; 32])))
+            .collect();
+
+        Box::leak(Box::new(KeyRing::parse(&lines.join("\n")).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn encrypted_round_trip() {
+        let dir = TempDir::new("encrypted");
+        let storage =
+            EncryptedStorage::new(Box::new(FsStorage::new(dir.0.clone())), key_ring(&["a"]));
+        round_trip(&storage).await;
+
+        storage.put("secret", b"plaintext").await.unwrap();
+        let raw = std::fs::read(dir.0.join("secret")).unwrap();
+        assert!(raw.starts_with(MAGIC));
+        assert!(!raw.windows(9).any(|w| w == b"plaintext"));
+    }
+
+    #[tokio::test]
+    async fn plaintext_and_old_keys_are_migrated() {
+        let dir = TempDir::new("rotation");
+        let inner = || Box::new(FsStorage::new(dir.0.clone()));
+
+        inner().put("plain", b"before encryption").await.unwrap();
+        EncryptedStorage::new(inner(), key_ring(&["old"]))
+            .put("rotated", b"old key")
+            .await
+            .unwrap();
+
+        let storage = EncryptedStorage::new(inner(), key_ring(&["old", "new"]));
+        assert_eq!(storage.head("plain").await.unwrap(), Some(17));
+        assert_eq!(
+            storage.get("plain", Some(7..17)).await.unwrap().as_deref(),
+            Some(&b"encryption"[..])
+        );
+        assert_eq!(
+            storage.get("rotated", None).await.unwrap().as_deref(),
+            Some(&b"old key"[..])
+        );
+
+        for key in ["plain", "rotated"] {
+            let raw = inner().get(key, None).await.unwrap().unwrap();
+            assert_eq!(&raw[..MAGIC.len() + 4], b"SCE1\x03new");
+        }
+        assert_eq!(storage.head("rotated").await.unwrap(), Some(7));
+    }
+
+    #[tokio::test]
+    async fn migrations_wait_for_writes() {
+        let dir = TempDir::new("migration");
+        let inner = || Box::new(FsStorage::new(dir.0.clone()));
+        inner().put("clip", b"plaintext").await.unwrap();
+        let storage = EncryptedStorage::new(inner(), key_ring(&["a"]));
+
+        // A delete in progress, the migration must not write the object back
+        let write = storage.writes.read().await;
+        assert_eq!(
+            storage.get("clip", None).await.unwrap().as_deref(),
+            Some(&b"plaintext"[..])
+        );
+        inner().delete("clip").await.unwrap();
+        drop(write);
+        assert_eq!(storage.get("clip", None).await.unwrap(), None);
+
+        // Without a concurrent write the object is migrated on the first read
+        inner().put("clip", b"plaintext").await.unwrap();
+        storage.get("clip", None).await.unwrap();
+        let raw = inner().get("clip", None).await.unwrap().unwrap();
+        assert!(raw.starts_with(MAGIC));
+    }
+
+    #[tokio::test]
+    async fn objects_are_bound_to_their_key() {
+        let dir = TempDir::new("swap");
+        let inner = || Box::new(FsStorage::new(dir.0.clone()));
+        let storage = EncryptedStorage::new(inner(), key_ring(&["a"]));
+
+        storage.put("a", b"clip a").await.unwrap();
+        let raw = inner().get("a", None).await.unwrap().unwrap();
+        inner().put("b", &raw).await.unwrap();
+
+        assert_eq!(storage.head("b").await.unwrap(), Some(6));
+        let e = storage.get("b", None).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        // Keys that were removed from the key file can't decrypt anything anymore
+        let rotated = EncryptedStorage::new(inner(), key_ring(&["b"]));
+        assert!(rotated.get("a", None).await.is_err());
+    }
+
+    #[test]
+    fn key_files() {
+        let key = general_purpose::STANDARD.encode([0u8; 32]);
+        let short = general_purpose::STANDARD.encode([0u8; 16]);
+
+        let ring = KeyRing::parse(&format!("# keys\n\nold {key}\nnew {key}\n")).unwrap();
+        assert_eq!(ring.active().0, "new");
+
+        let error = |s: String| KeyRing::parse(&s).err().unwrap();
+        assert_eq!(error(String::new()), "Key file contains no keys");
+        assert_eq!(
+            error(format!("a {key}\nb")),
+            "Line 2: expected \"<key id> <key>\""
+        );
+        assert_eq!(error(format!("a {short}")), "Line 1: keys must be 32 bytes");
+        assert!(error("a not-base64".to_owned()).starts_with("Line 1: invalid key"));
+        assert_eq!(
+            error(format!("a {key}\na {key}")),
+            "Line 2: invalid or duplicate key id a"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use super::{Storage, StorageResult};
+
+/// Stores every object as a file in a directory
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+}
+
+#[async_trait]
+impl Storage for FsStorage {
+    async fn put(&self, key: &str, data: &[u8]) -> StorageResult<()> {
+        let path = self.root.join(key);
+        // Every put gets its own temporary file, so concurrent puts of a key can't mix
+        let mut suffix = [0u8; 8];
+        getrandom::fill(&mut suffix).map_err(std::io::Error::other)?;
+        let suffix: String = suffix.iter().map(|b| format!("{b:02x}")).collect();
+        let tmp_path = self.root.join(format!("{key}.{suffix}.tmp"));
+
+        // Readers never see partially written files
+        let result = match tokio::fs::write(&tmp_path, data).await {
+            Ok(()) => tokio::fs::rename(&tmp_path, &path).await,
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+        }
+
+        result
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> StorageResult<Option<Vec<u8>>> {
+        let path = self.root.join(key);
+
+        let Some(range) = range else {
+            return match tokio::fs::read(&path).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            };
+        };
+
+        let mut file = match tokio::fs::File::open(&path).await {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        file.seek(SeekFrom::Start(range.start)).await?;
+
+        let mut data = Vec::with_capacity((range.end - range.start) as usize);
+        file.take(range.end - range.start)
+            .read_to_end(&mut data)
+            .await?;
+
+        Ok(Some(data))
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<u64>> {
+        match tokio::fs::metadata(self.root.join(key)).await {
+            Ok(m) => Ok(Some(m.len())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        match tokio::fs::remove_file(self.root.join(key)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> StorageResult<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut dir_entries = tokio::fs::read_dir(&self.root).await?;
+
+        while let Some(entry) = dir_entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            // Skip temporary files and probes like `.readyz`
+            if name.starts_with('.') || name.ends_with(".tmp") {
+                continue;
+            }
+            keys.push(name);
+        }
+
+        Ok(keys)
+    }
+
+    fn available_space(&self) -> Option<std::io::Result<u64>> {
+        Some(fs4::available_space(&self.root))
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::FsStorage;
+    use crate::storage::tests::round_trip;
+    use crate::storage::Storage;
+
+    /// Empty directory that is removed again when dropped
+    pub struct TempDir(pub std::path::PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("short-clip-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn fs_round_trip() {
+        let dir = TempDir::new("fs");
+        round_trip(&FsStorage::new(dir.0.clone())).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_puts() {
+        let dir = TempDir::new("concurrent");
+        let storage = std::sync::Arc::new(FsStorage::new(dir.0.clone()));
+
+        let puts: Vec<_> = (0..16u8)
+            .map(|i| {
+                let storage = storage.clone();
+                tokio::spawn(async move { storage.put("key", &[i; 4096]).await })
+            })
+            .collect();
+        for put in puts {
+            put.await.unwrap().unwrap();
+        }
+
+        // One of the puts won as a whole and no temporary files are left behind
+        let data = storage.get("key", None).await.unwrap().unwrap();
+        assert!(data.iter().all(|b| *b == data[0]));
+        assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,127 @@
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use crate::util::content_path;
+
+mod encrypted;
+mod fs;
+mod s3;
+
+pub use encrypted::{generate_key, open_value, seal_value, KEYS_VAR};
+
+/// Storage errors are plain IO errors, so they convert into every error type used by the server
+pub type StorageResult<T> = std::io::Result<T>;
+
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
+
+/// Held while the metadata of a clip is read, changed and written back, so concurrent changes aren't lost
+pub static REWRITE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Backend storing clip data and metadata as objects identified by a key
+///
+/// Clip data is stored under its id and the metadata under `<id>.json`
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores an object, replacing an existing one atomically
+    async fn put(&self, key: &str, data: &[u8]) -> StorageResult<()>;
+
+    /// Reads an object or only the given byte range of it, `None` if it doesn't exist
+    ///
+    /// The range has to lie within the object
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> StorageResult<Option<Vec<u8>>>;
+
+    /// Size of an object in bytes, `None` if it doesn't exist
+    async fn head(&self, key: &str) -> StorageResult<Option<u64>>;
+
+    /// Deletes an object, missing objects are ignored
+    async fn delete(&self, key: &str) -> StorageResult<()>;
+
+    /// Keys of all stored objects
+    async fn list(&self) -> StorageResult<Vec<String>>;
+
+    /// Free space left for new objects, `None` if the backend has no such limit
+    fn available_space(&self) -> Option<std::io::Result<u64>> {
+        None
+    }
+}
+
+/// Key of the metadata object belonging to a clip
+pub fn metadata_key(id: &str) -> String {
+    format!("{id}.json")
+}
+
+/// Sets up the storage backend selected through `STORAGE`
+///
+/// `fs` (default) stores clips in the `contents` directory, `s3` in an
+/// S3 compatible bucket. If `ENCRYPTION_KEYS` is set all objects are encrypted
+pub fn init() -> StorageResult<()> {
+    if STORAGE.get().is_some() {
+        return Ok(());
+    }
+
+    let backend: Box<dyn Storage> = match std::env::var("STORAGE").as_deref() {
+        Ok("fs") | Err(_) => Box::new(fs::FsStorage::new(content_path())),
+        Ok("s3") => Box::new(s3::S3Storage::from_env()?),
+        Ok(other) => {
+            return Err(std::io::Error::other(format!(
+                "Unknown storage backend {other}"
+            )))
+        }
+    };
+
+    let backend = match encrypted::load_key_ring()? {
+        Some(keys) => Box::new(encrypted::EncryptedStorage::new(backend, keys)),
+        None => backend,
+    };
+
+    let _ = STORAGE.set(backend);
+
+    Ok(())
+}
+
+/// The configured storage backend, `init` has to be called first
+pub fn storage() -> &'static dyn Storage {
+    STORAGE.get().expect("Storage not initialised").as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Storage;
+
+    /// Exercises every operation of an empty backend
+    pub async fn round_trip(storage: &dyn Storage) {
+        assert_eq!(storage.get("abc", None).await.unwrap(), None);
+        assert_eq!(storage.head("abc").await.unwrap(), None);
+        assert!(storage.list().await.unwrap().is_empty());
+
+        storage.put("abc", b"hello world").await.unwrap();
+        storage.put("abc.json", b"{}").await.unwrap();
+        assert_eq!(
+            storage.get("abc", None).await.unwrap().as_deref(),
+            Some(&b"hello world"[..])
+        );
+        assert_eq!(
+            storage.get("abc", Some(6..11)).await.unwrap().as_deref(),
+            Some(&b"world"[..])
+        );
+        assert_eq!(storage.head("abc").await.unwrap(), Some(11));
+
+        let mut keys = storage.list().await.unwrap();
+        keys.sort();
+        assert_eq!(keys, ["abc", "abc.json"]);
+
+        storage.put("abc", b"replaced").await.unwrap();
+        assert_eq!(
+            storage.get("abc", None).await.unwrap().as_deref(),
+            Some(&b"replaced"[..])
+        );
+
+        storage.delete("abc").await.unwrap();
+        storage.delete("abc").await.unwrap();
+        assert_eq!(storage.get("abc", None).await.unwrap(), None);
+        assert_eq!(storage.list().await.unwrap(), ["abc.json"]);
+    }
+}
@@ -0,0 +1,266 @@
+use std::io::Error;
+use std::ops::Range;
+
+use async_trait::async_trait;
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+
+use super::{Storage, StorageResult};
+
+/// Stores objects in an S3 compatible bucket, e.g. AWS S3 or MinIO
+///
+/// Configured through `S3_BUCKET`, `S3_REGION` (default `us-east-1`),
+/// `S3_ENDPOINT` for other providers than AWS, `S3_PREFIX` for the keys
+/// and `S3_ACCESS_KEY_ID` / `S3_SECRET_ACCESS_KEY`, which fall back to the
+/// usual AWS environment variables and profiles
+pub struct S3Storage {
+    bucket: Box<Bucket>,
+    prefix: String,
+}
+
+impl S3Storage {
+    pub fn from_env() -> StorageResult<Self> {
+        let var = |name: &str| std::env::var(name).ok();
+
+        let name = var("S3_BUCKET").ok_or_else(|| Error::other("S3_BUCKET is not set"))?;
+        let region_name = var("S3_REGION").unwrap_or("us-east-1".to_owned());
+        let region = match var("S3_ENDPOINT") {
+            Some(endpoint) => Region::Custom {
+                region: region_name,
+                endpoint,
+            },
+            None => region_name.parse().map_err(Error::other)?,
+        };
+        let credentials = match var("S3_ACCESS_KEY_ID") {
+            Some(access_key) => Credentials::new(
+                Some(&access_key),
+                var("S3_SECRET_ACCESS_KEY").as_deref(),
+                None,
+                None,
+                None,
+            )
+            .map_err(Error::other)?,
+            None => Credentials::default().map_err(Error::other)?,
+        };
+
+        let mut bucket = Bucket::new(&name, region, credentials).map_err(Error::other)?;
+        // Custom endpoints like MinIO usually don't support virtual host style urls
+        if var("S3_ENDPOINT").is_some() {
+            bucket = bucket.with_path_style();
+        }
+
+        Ok(Self {
+            bucket,
+            prefix: var("S3_PREFIX").unwrap_or_default(),
+        })
+    }
+
+    fn path(&self, key: &str) -> String {
+        format!("{}{key}", self.prefix)
+    }
+}
+
+fn check_status(op: &str, key: &str, status: u16) -> StorageResult<()> {
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(Error::other(format!(
+            "S3 {op} of {key} failed with status {status}"
+        )))
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: &[u8]) -> StorageResult<()> {
+        let resp = self
+            .bucket
+            .put_object(self.path(key), data)
+            .await
+            .map_err(Error::other)?;
+
+        check_status("PUT", key, resp.status_code())
+    }
+
+    async fn get(&self, key: &str, range: Option<Range<u64>>) -> StorageResult<Option<Vec<u8>>> {
+        let resp = match range {
+            // S3 ranges include the end
+            Some(range) if range.is_empty() => return Ok(Some(Vec::new())),
+            Some(range) => self
+                .bucket
+                .get_object_range(self.path(key), range.start, Some(range.end - 1))
+                .await
+                .map_err(Error::other)?,
+            None => self
+                .bucket
+                .get_object(self.path(key))
+                .await
+                .map_err(Error::other)?,
+        };
+
+        if resp.status_code() == 404 {
+            return Ok(None);
+        }
+        check_status("GET", key, resp.status_code())?;
+
+        Ok(Some(resp.to_vec()))
+    }
+
+    async fn head(&self, key: &str) -> StorageResult<Option<u64>> {
+        let (head, status) = self
+            .bucket
+            .head_object(self.path(key))
+            .await
+            .map_err(Error::other)?;
+
+        if status == 404 {
+            return Ok(None);
+        }
+        check_status("HEAD", key, status)?;
+
+        Ok(Some(head.content_length.unwrap_or_default() as u64))
+    }
+
+    async fn delete(&self, key: &str) -> StorageResult<()> {
+        let resp = self
+            .bucket
+            .delete_object(self.path(key))
+            .await
+            .map_err(Error::other)?;
+
+        match resp.status_code() {
+            404 => Ok(()),
+            status => check_status("DELETE", key, status),
+        }
+    }
+
+    async fn list(&self) -> StorageResult<Vec<String>> {
+        let pages = self
+            .bucket
+            .list(self.prefix.clone(), None)
+            .await
+            .map_err(Error::other)?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .filter_map(|object| object.key.strip_prefix(&self.prefix).map(|k| k.to_owned()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::{Bytes, Incoming};
+    use hyper::service::service_fn;
+    use hyper::{Method, Request, Response};
+    use hyper_util::rt::TokioIo;
+
+    use super::*;
+    use crate::storage::tests::round_trip;
+
+    type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+    /// Answers the few S3 requests the backend sends from an in-memory map
+    async fn mock_s3(
+        req: Request<Incoming>,
+        objects: Objects,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        let method = req.method().clone();
+        let key = req.uri().path().trim_start_matches("/bucket/").to_owned();
+        let query = req.uri().query().unwrap_or_default().to_owned();
+        let range = req
+            .headers()
+            .get("range")
+            .map(|v| v.to_str().unwrap().to_owned());
+        let body = req.into_body().collect().await.unwrap().to_bytes();
+
+        let mut objects = objects.lock().unwrap();
+        let resp = Response::builder();
+        let resp = match (method, objects.get(&key)) {
+            (Method::GET, _) if query.contains("list-type=2") => {
+                let contents: String = objects
+                    .iter()
+                    .map(|(key, data)| {
+                        format!(
+                            "<Contents><Key>{key}</Key><LastModified>2024-01-01T00:00:00.000Z</LastModified>\
+                            <ETag>\"etag\"</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+                            data.len()
+                        )
+                    })
+                    .collect();
+                let xml = format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?><ListBucketResult><Name>bucket</Name>\
+                    <Prefix></Prefix><KeyCount>{}</KeyCount><MaxKeys>1000</MaxKeys>\
+                    <IsTruncated>false</IsTruncated>{contents}</ListBucketResult>",
+                    objects.len()
+                );
+                resp.body(Full::new(Bytes::from(xml)))
+            }
+            (Method::PUT, _) => {
+                objects.insert(key, body.to_vec());
+                resp.header("etag", "\"etag\"").body(Full::default())
+            }
+            (Method::DELETE, _) => {
+                objects.remove(&key);
+                resp.status(204).body(Full::default())
+            }
+            (Method::HEAD, Some(data)) => resp
+                .header("content-length", data.len())
+                .body(Full::default()),
+            (Method::GET, Some(data)) => match range {
+                Some(range) => {
+                    let (start, end) = range
+                        .strip_prefix("bytes=")
+                        .and_then(|r| r.split_once('-'))
+                        .unwrap();
+                    let start: usize = start.parse().unwrap();
+                    let end: usize = end.parse().unwrap();
+                    resp.status(206)
+                        .body(Full::new(Bytes::from(data[start..=end].to_vec())))
+                }
+                None => resp.body(Full::new(Bytes::from(data.clone()))),
+            },
+            _ => resp.status(404).body(Full::default()),
+        };
+
+        Ok(resp.unwrap())
+    }
+
+    #[tokio::test]
+    async fn s3_round_trip() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let objects = Objects::default();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let objects = objects.clone();
+                tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(
+                    TokioIo::new(stream),
+                    service_fn(move |req| mock_s3(req, objects.clone())),
+                ));
+            }
+        });
+
+        let region = Region::Custom {
+            region: "us-east-1".to_owned(),
+            endpoint,
+        };
+        let credentials = Credentials::new(Some("key"), Some("secret"), None, None, None).unwrap();
+        let storage = S3Storage {
+            bucket: Bucket::new("bucket", region, credentials)
+                .unwrap()
+                .with_path_style(),
+            prefix: "clips/".to_owned(),
+        };
+
+        round_trip(&storage).await;
+    }
+}
@@ -0,0 +1,192 @@
+//! Setup shared by the handler tests
+//!
+//! The server keeps its configuration in process wide globals, so all tests share one
+//! instance running in a temporary directory. Tests stay independent of each other by
+//! using their own users from `TOKENS` and their own client IPs from `new_ip`
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::http::request::Builder;
+use hyper::{Method, Request, Response};
+use shared::api::{routes, UploadResponse};
+use tokio::sync::{mpsc, OnceCell};
+
+use crate::{audit, db, health, quota, ratelimit, storage, tokens};
+
+/// Tokens file of the test server, the token of every user is `<username>-token`
+const TOKENS: &str = "\
+# Users of the handler tests
+admin-token admin scopes=admin
+quota-token quota max_clips=2 max_bytes=12 max_upload=8
+limited-token limited
+limited-second-token limited
+expired-token expired expires=1
+purged-token purged
+deleter-token deleter
+other-token other
+uploader-token uploader scopes=upload
+reader-token reader scopes=read-private
+red-token red teams=red
+purple-token purple teams=red,blue
+blue-token blue teams=blue
+protector-token protector
+burner-token burner
+restricted-token restricted content_types=text/* max_ttl=1h
+";
+
+/// Variables that would change the behaviour of the test server if set in the environment
+const IGNORED_VARS: &[&str] = &[
+    "STORAGE",
+    "ENCRYPTION_KEYS",
+    "METADATA_STORE",
+    "DATABASE_PATH",
+    "TRUST_PROXY",
+    "MIN_FREE_SPACE",
+    "QUOTA_MAX_BYTES",
+    "QUOTA_MAX_CLIPS",
+    "QUOTA_MAX_UPLOAD",
+];
+
+static SETUP: OnceCell<()> = OnceCell::const_new();
+
+/// Entries received from the audit log so far and the receiver of new ones
+static AUDIT_LOG: Mutex<(Vec<String>, Option<mpsc::Receiver<String>>)> =
+    Mutex::new((Vec::new(), None));
+
+/// Initialises the test server once, every helper below calls this
+pub async fn setup() {
+    SETUP
+        .get_or_init(|| async {
+            let dir =
+                std::env::temp_dir().join(format!("short-clip-server-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(dir.join("contents")).unwrap();
+            // Clips and the tokens file are always relative to the working directory
+            std::env::set_current_dir(&dir).unwrap();
+            std::fs::write(".authorized_tokens", TOKENS).unwrap();
+
+            for var in IGNORED_VARS {
+                std::env::remove_var(var);
+            }
+            // Every password attempt runs argon2, which is slow without optimisations
+            std::env::set_var("PASSWORD_BURST", "2");
+
+            health::init().unwrap();
+            quota::init().unwrap();
+            ratelimit::init().unwrap();
+            storage::init().unwrap();
+            db::init().unwrap();
+            tokens::load().await.unwrap();
+            AUDIT_LOG.lock().unwrap().1 = Some(audit::capture());
+        })
+        .await;
+}
+
+/// A client IP no other test uses, so rate limits don't carry over between tests
+pub fn new_ip() -> IpAddr {
+    static NEXT: AtomicU32 = AtomicU32::new(1);
+
+    IpAddr::V4(Ipv4Addr::from(
+        u32::from(Ipv4Addr::new(10, 0, 0, 0)) + NEXT.fetch_add(1, Ordering::Relaxed),
+    ))
+}
+
+/// Builder of a request authenticated as `user`, anonymous if it is `None`
+pub fn request(method: Method, path: &str, user: Option<&str>) -> Builder {
+    let builder = Request::builder().method(method).uri(path);
+
+    match user {
+        Some(user) => builder.header(AUTHORIZATION, format!("Bearer {user}-token")),
+        None => builder,
+    }
+}
+
+/// Sends a request from `ip` through the router
+pub async fn send(ip: IpAddr, req: Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+    setup().await;
+
+    crate::handle_req(req, SocketAddr::new(ip, 40000))
+        .await
+        .unwrap()
+}
+
+/// Sends a `GET` request for `path` from `ip` as `user`
+pub async fn get(ip: IpAddr, path: &str, user: Option<&str>) -> Response<Full<Bytes>> {
+    send(
+        ip,
+        request(Method::GET, path, user)
+            .body(Full::default())
+            .unwrap(),
+    )
+    .await
+}
+
+/// Sends a `DELETE` request for `path` from `ip` as `user`
+pub async fn delete(ip: IpAddr, path: &str, user: Option<&str>) -> Response<Full<Bytes>> {
+    send(
+        ip,
+        request(Method::DELETE, path, user)
+            .body(Full::default())
+            .unwrap(),
+    )
+    .await
+}
+
+/// Uploads `data` as `user` from `ip`, `headers` are added to the request
+pub async fn upload(
+    ip: IpAddr,
+    user: &str,
+    data: &str,
+    headers: &[(&str, &str)],
+) -> Response<Full<Bytes>> {
+    let mut req =
+        request(Method::POST, routes::UPLOAD, Some(user)).header(CONTENT_TYPE, "text/plain");
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+
+    send(
+        ip,
+        req.body(Full::new(Bytes::from(data.to_owned()))).unwrap(),
+    )
+    .await
+}
+
+/// Id of the clip created by a successful upload
+pub async fn uploaded_id(resp: Response<Full<Bytes>>) -> String {
+    assert_eq!(resp.status(), 201);
+    let body: UploadResponse = serde_json::from_str(&text(resp).await).unwrap();
+
+    body.id
+}
+
+/// The body of a response
+pub async fn text(resp: Response<Full<Bytes>>) -> String {
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+/// Entries of the audit log about requests from `ip`
+pub fn audit_log(ip: IpAddr) -> Vec<String> {
+    let mut log = AUDIT_LOG.lock().unwrap();
+    let (entries, receiver) = &mut *log;
+
+    if let Some(receiver) = receiver {
+        while let Ok(entry) = receiver.try_recv() {
+            entries.push(entry);
+        }
+    }
+
+    let ip = format!(" ip={ip} ");
+    entries
+        .iter()
+        .filter(|e| e.contains(&ip))
+        .cloned()
+        .collect()
+}
@@ -0,0 +1,873 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use argon2::password_hash::phc::PasswordHash;
+use argon2::password_hash::PasswordVerifier;
+use argon2::Argon2;
+use base64::{engine::general_purpose, Engine as _};
+use hyper::header::AUTHORIZATION;
+use hyper::Request;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::io::AsyncWriteExt;
+
+use crate::audit;
+use crate::quota::Quota;
+use crate::ratelimit::{auth_limiter, RateLimiter};
+use crate::util::{parse_duration, unix_time};
+
+const TOKENS_PATH: &str = "./.authorized_tokens";
+
+/// How often the tokens file is checked for modifications
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Maximum number of tokens remembered as verified against an argon2 hash
+const VERIFIED_CACHE_SIZE: usize = 1024;
+
+static TOKEN_STORE: RwLock<Option<Arc<TokenStore>>> = RwLock::new(None);
+
+/// Error of the last attempt to load the tokens file, `None` if it succeeded
+static LOAD_ERROR: RwLock<Option<String>> = RwLock::new(None);
+
+/// The user a token belongs to together with its settings
+#[derive(Debug)]
+pub struct TokenEntry {
+    pub username: String,
+    /// Per user overrides of the default quota
+    pub quota: Quota,
+    /// Actions this token is allowed to perform
+    pub scopes: Vec<Scope>,
+    /// Unix timestamp after which the token is no longer accepted
+    pub expires_at: Option<u64>,
+    /// Mime types this token may upload, `*` wildcards like `image/*` are allowed
+    pub content_types: Option<Vec<String>>,
+    /// Maximum ttl in seconds of uploads with this token
+    pub max_ttl: Option<u64>,
+    /// Teams whose members can view each others private clips
+    pub teams: Vec<String>,
+}
+
+impl TokenEntry {
+    fn new(username: &str) -> Self {
+        Self {
+            username: username.to_owned(),
+            quota: Quota::default(),
+            scopes: Scope::DEFAULT.to_vec(),
+            expires_at: None,
+            content_types: None,
+            max_ttl: None,
+            teams: Vec::new(),
+        }
+    }
+
+    /// Applies a `key=value` option from the tokens file
+    fn parse_option(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if self.quota.parse_option(key, value)? {
+            return Ok(());
+        }
+
+        let invalid = || format!("Invalid value for {key}: {value}");
+        match key {
+            "scopes" => {
+                self.scopes = value
+                    .split(',')
+                    .map(|s| s.parse())
+                    .collect::<Result<_, _>>()?;
+            }
+            "expires" => self.expires_at = Some(value.parse().map_err(|_| invalid())?),
+            "content_types" => {
+                self.content_types = Some(value.split(',').map(|s| s.to_owned()).collect());
+            }
+            "max_ttl" => self.max_ttl = Some(parse_duration(value).ok_or_else(invalid)?),
+            "teams" => self.teams = value.split(',').map(|s| s.to_owned()).collect(),
+            _ => return Err(format!("Unknown option \"{key}\"")),
+        }
+
+        Ok(())
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| unix_time() > expires_at)
+    }
+
+    /// Whether this token may view a private clip of `author`
+    pub fn can_read_private(&self, author: &str) -> bool {
+        if !self.has_scope(Scope::ReadPrivate) {
+            return false;
+        }
+        if self.username == author || self.has_scope(Scope::Admin) {
+            return true;
+        }
+
+        let author_teams = user_teams(author);
+        self.teams.iter().any(|team| author_teams.contains(team))
+    }
+
+    /// Whether this token may upload content of the given mime type
+    pub fn allows_content_type(&self, content_type: &str) -> bool {
+        let content_types = match &self.content_types {
+            Some(c) => c,
+            None => return true,
+        };
+        // Ignore parameters like `; charset=utf-8`
+        let content_type = content_type.split(';').next().unwrap_or_default().trim();
+
+        content_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix('*') {
+                Some(prefix) => content_type.starts_with(prefix),
+                None => content_type.eq_ignore_ascii_case(allowed),
+            })
+    }
+}
+
+/// Permission granted to a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Upload new clips
+    Upload,
+    /// View private clips
+    ReadPrivate,
+    /// Delete own clips
+    Delete,
+    /// Everything, including deleting and inspecting the clips of other users
+    Admin,
+}
+
+impl Scope {
+    /// Scopes of tokens without a `scopes` option
+    pub const DEFAULT: &'static [Scope] = &[Scope::Upload, Scope::ReadPrivate, Scope::Delete];
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upload" => Ok(Scope::Upload),
+            "read-private" => Ok(Scope::ReadPrivate),
+            "delete" => Ok(Scope::Delete),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(format!("Unknown scope \"{s}\"")),
+        }
+    }
+}
+
+/// How a token is stored in the tokens file
+#[derive(Clone)]
+pub enum TokenSecret {
+    /// The raw token
+    Plain(String),
+    /// `sha256:<hex digest>` of the token
+    Sha256([u8; 32]),
+    /// An argon2 PHC string like `$argon2id$v=19$...`
+    Argon2(Box<PasswordHash>),
+}
+
+impl TokenSecret {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if let Some(hex) = s.strip_prefix("sha256:") {
+            parse_digest(hex)
+                .map(TokenSecret::Sha256)
+                .ok_or(format!("Invalid sha256 digest: {hex}"))
+        } else if s.starts_with("$argon2") {
+            PasswordHash::new(s)
+                .map(|hash| TokenSecret::Argon2(Box::new(hash)))
+                .map_err(|e| format!("Invalid argon2 hash: {e}"))
+        } else {
+            Ok(TokenSecret::Plain(s.to_owned()))
+        }
+    }
+}
+
+/// Error in a specific line of the tokens file
+#[derive(Debug)]
+pub struct TokenFileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TokenFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{TOKENS_PATH}:{}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for TokenFileError {}
+
+/// All tokens of the tokens file, indexed by the way they are stored
+#[derive(Default)]
+pub struct TokenStore {
+    /// sha256 digests of plain and sha256 hashed tokens
+    digests: Vec<([u8; 32], Arc<TokenEntry>)>,
+    argon2: Vec<(Box<PasswordHash>, Arc<TokenEntry>)>,
+    /// Teams of every user, combined over all their tokens
+    teams: HashMap<String, Vec<String>>,
+    /// Digests of tokens that were already verified against an argon2 hash,
+    /// argon2 is deliberately slow so each token is only verified once
+    ///
+    /// Only valid tokens end up here, the size is still capped at `VERIFIED_CACHE_SIZE`
+    argon2_verified: Mutex<HashMap<[u8; 32], Arc<TokenEntry>>>,
+}
+
+/// A line of the tokens file, kept verbatim so the file can be written back
+pub enum TokenLine {
+    /// Comment or empty line
+    Other(String),
+    Entry(TokenFileEntry),
+}
+
+/// A token line of the tokens file
+pub struct TokenFileEntry {
+    /// The token or its hash as written in the file
+    pub secret: String,
+    pub username: String,
+    /// `key=value` options as written in the file
+    pub options: Vec<String>,
+    parsed_secret: TokenSecret,
+    entry: Arc<TokenEntry>,
+}
+
+impl TokenFileEntry {
+    pub fn new(secret: &str, username: &str, options: Vec<String>) -> Result<Self, String> {
+        let mut entry = TokenEntry::new(username);
+        for option in &options {
+            let (key, value) = option
+                .split_once('=')
+                .ok_or(format!("Expected key=value, found \"{option}\""))?;
+
+            entry.parse_option(key, value)?;
+        }
+
+        Ok(Self {
+            secret: secret.to_owned(),
+            username: username.to_owned(),
+            options,
+            parsed_secret: TokenSecret::parse(secret)?,
+            entry: Arc::new(entry),
+        })
+    }
+
+    /// Settings of this token
+    pub fn entry(&self) -> &TokenEntry {
+        &self.entry
+    }
+
+    /// Human readable description of how the token is stored
+    pub fn kind(&self) -> &'static str {
+        match self.parsed_secret {
+            TokenSecret::Plain(_) => "plain",
+            TokenSecret::Sha256(_) => "sha256",
+            TokenSecret::Argon2(_) => "argon2",
+        }
+    }
+}
+
+/// The tokens file
+///
+/// Every line has the format `<token> <username> [key=value ...]`,
+/// empty lines and lines starting with `#` are ignored
+pub struct TokenFile {
+    pub lines: Vec<TokenLine>,
+}
+
+impl TokenFile {
+    pub fn parse(s: &str) -> Result<Self, TokenFileError> {
+        let mut lines = Vec::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let err = |message: String| TokenFileError {
+                line: i + 1,
+                message,
+            };
+
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                lines.push(TokenLine::Other(line.to_owned()));
+                continue;
+            }
+
+            let mut parts = trimmed.split_whitespace();
+            let (secret, username) = match (parts.next(), parts.next()) {
+                (Some(secret), Some(username)) => (secret, username),
+                _ => {
+                    return Err(err(
+                        "Expected a line of \"<token> <username> [key=value ...]\"".to_owned(),
+                    ))
+                }
+            };
+            let options = parts.map(|o| o.to_owned()).collect();
+
+            lines.push(TokenLine::Entry(
+                TokenFileEntry::new(secret, username, options).map_err(err)?,
+            ));
+        }
+
+        Ok(Self { lines })
+    }
+
+    /// Reads and parses the tokens file
+    pub async fn read() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let str = tokio::fs::read_to_string(TOKENS_PATH).await?;
+
+        Ok(Self::parse(&str)?)
+    }
+
+    /// Replaces the tokens file atomically
+    ///
+    /// The new contents are written to a temporary file that is then renamed,
+    /// so a running server never reads a partially written file
+    pub async fn write(&self) -> Result<(), std::io::Error> {
+        let tmp_path = format!("{TOKENS_PATH}.tmp");
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // Tokens should only be readable by the server user
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&tmp_path).await?;
+        file.write_all(self.to_string().as_bytes()).await?;
+        file.sync_all().await?;
+
+        tokio::fs::rename(&tmp_path, TOKENS_PATH).await
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &TokenFileEntry> {
+        self.lines.iter().filter_map(|l| match l {
+            TokenLine::Entry(e) => Some(e),
+            TokenLine::Other(_) => None,
+        })
+    }
+}
+
+impl fmt::Display for TokenFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match line {
+                TokenLine::Other(l) => writeln!(f, "{l}")?,
+                TokenLine::Entry(e) => {
+                    write!(f, "{} {}", e.secret, e.username)?;
+                    for option in &e.options {
+                        write!(f, " {option}")?;
+                    }
+                    writeln!(f)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TokenStore {
+    pub fn new(file: &TokenFile) -> Self {
+        let mut store = TokenStore::default();
+
+        for e in file.entries() {
+            let entry = e.entry.clone();
+
+            let teams = store.teams.entry(e.username.clone()).or_default();
+            teams.extend(entry.teams.iter().cloned());
+
+            match &e.parsed_secret {
+                // Plain tokens are only kept as digests so all comparisons have the same length
+                TokenSecret::Plain(token) => store
+                    .digests
+                    .push((Sha256::digest(token.as_bytes()).into(), entry)),
+                TokenSecret::Sha256(digest) => store.digests.push((*digest, entry)),
+                TokenSecret::Argon2(hash) => store.argon2.push((hash.clone(), entry)),
+            }
+        }
+
+        store
+    }
+
+    /// Finds the entry belonging to a raw token among the digests and verified argon2 tokens
+    ///
+    /// The digest of the token is compared in constant time against every
+    /// stored digest, so the response time doesn't reveal how much of a token matched
+    pub fn lookup(&self, token: &str) -> Option<Arc<TokenEntry>> {
+        let digest: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+
+        let mut found = None;
+        for (stored, entry) in &self.digests {
+            if bool::from(stored.ct_eq(&digest)) {
+                found = Some(entry.clone());
+            }
+        }
+        if found.is_some() {
+            return found;
+        }
+
+        self.argon2_verified.lock().unwrap().get(&digest).cloned()
+    }
+
+    /// Verifies a token that `lookup` didn't find against every argon2 hash
+    ///
+    /// Argon2 is slow, so this runs on a blocking thread
+    pub async fn verify_argon2(self: Arc<Self>, token: &str) -> Option<Arc<TokenEntry>> {
+        if self.argon2.is_empty() {
+            return None;
+        }
+
+        let store = self.clone();
+        let owned_token = token.to_owned();
+        let entry = tokio::task::spawn_blocking(move || {
+            let argon2 = Argon2::default();
+            store
+                .argon2
+                .iter()
+                .find(|(hash, _)| {
+                    argon2
+                        .verify_password(owned_token.as_bytes(), hash.as_ref())
+                        .is_ok()
+                })
+                .map(|(_, entry)| entry.clone())
+        })
+        .await
+        .ok()??;
+
+        let mut verified = self.argon2_verified.lock().unwrap();
+        if verified.len() >= VERIFIED_CACHE_SIZE {
+            verified.clear();
+        }
+        verified.insert(Sha256::digest(token.as_bytes()).into(), entry.clone());
+
+        Some(entry)
+    }
+
+    /// Finds the entry of a token, verifying it against the argon2 hashes if `lookup` fails
+    ///
+    /// Unknown tokens are limited through the bucket of `key`. A token is taken before
+    /// verifying, so concurrent requests can't queue up more argon2 work than the limit
+    /// allows, and refunded if the token turns out to be valid
+    pub async fn find(
+        self: Arc<Self>,
+        token: &str,
+        limiter: &RateLimiter,
+        key: &str,
+    ) -> Result<Arc<TokenEntry>, AuthError> {
+        if let Some(entry) = self.lookup(token) {
+            return Ok(entry);
+        }
+
+        limiter
+            .check(key, 1.0)
+            .map_err(AuthError::TooManyAttempts)?;
+
+        match self.verify_argon2(token).await {
+            Some(entry) => {
+                limiter.refund(key, 1.0);
+                Ok(entry)
+            }
+            None => Err(AuthError::InvalidToken),
+        }
+    }
+}
+
+/// Loads the tokens file, replacing the current token store on success
+pub async fn load() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let store = match TokenFile::read().await {
+        Ok(file) => TokenStore::new(&file),
+        Err(e) => {
+            *LOAD_ERROR.write().unwrap() = Some(e.to_string());
+            return Err(e);
+        }
+    };
+
+    *TOKEN_STORE.write().unwrap() = Some(Arc::new(store));
+    *LOAD_ERROR.write().unwrap() = None;
+
+    Ok(())
+}
+
+/// Reloads the tokens file whenever it changes or the process receives SIGHUP
+///
+/// If the new file is invalid the previous tokens stay active
+pub fn spawn_reloader() {
+    tokio::spawn(async {
+        let mut last_modified = modified_time().await;
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let modified = modified_time().await;
+            if modified != last_modified {
+                last_modified = modified;
+                reload().await;
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    tokio::spawn(async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Unable to listen for SIGHUP: {e}");
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            reload().await;
+        }
+    });
+}
+
+async fn reload() {
+    match load().await {
+        Ok(_) => println!("Reloaded {TOKENS_PATH}"),
+        Err(e) => eprintln!("Failed to reload {TOKENS_PATH}, keeping previous tokens: {e}"),
+    }
+}
+
+async fn modified_time() -> Option<SystemTime> {
+    tokio::fs::metadata(TOKENS_PATH).await.ok()?.modified().ok()
+}
+
+/// Teams the user is a member of according to the current tokens file
+pub fn user_teams(username: &str) -> Vec<String> {
+    TOKEN_STORE
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|store| store.teams.get(username).cloned())
+        .unwrap_or_default()
+}
+
+/// `Ok` if the current tokens file was loaded, the error of the last attempt otherwise
+///
+/// A broken file keeps the previous tokens active, but is still reported here
+pub fn tokens_status() -> Result<(), String> {
+    if let Some(e) = LOAD_ERROR.read().unwrap().as_ref() {
+        return Err(e.clone());
+    }
+    if TOKEN_STORE.read().unwrap().is_none() {
+        return Err("never loaded".to_owned());
+    }
+
+    Ok(())
+}
+
+/// Reason why a request couldn't be authenticated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// No Authorization header was sent
+    Missing,
+    /// The Authorization header isn't of the form `Bearer <token>`
+    Malformed,
+    /// The token is unknown
+    InvalidToken,
+    /// The token is known but expired
+    Expired,
+    /// The client IP sent too many unknown tokens, contains the time until it may try again
+    TooManyAttempts(Duration),
+}
+
+impl AuthError {
+    /// The `error` attribute of the `WWW-Authenticate` header as defined in RFC 6750
+    pub fn error_code(&self) -> Option<&'static str> {
+        match self {
+            AuthError::Missing => None,
+            AuthError::Malformed => Some("invalid_request"),
+            AuthError::InvalidToken | AuthError::Expired => Some("invalid_token"),
+            AuthError::TooManyAttempts(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuthError::Missing => "missing Authorization header",
+            AuthError::Malformed => "malformed Authorization header",
+            AuthError::InvalidToken => "invalid token",
+            AuthError::Expired => "expired token",
+            AuthError::TooManyAttempts(_) => "too many failed attempts",
+        })
+    }
+}
+
+/// Looks up the token entry for the `Authorization: Bearer <token>` header of a request
+///
+/// Rejected tokens are recorded in the audit log together with the client IP,
+/// unknown tokens are additionally rate limited per IP. Requests without any
+/// token, like anonymous downloads, aren't failed attempts and stay out of the log
+pub async fn authenticate<B>(req: &Request<B>, ip: IpAddr) -> Result<Arc<TokenEntry>, AuthError> {
+    let result = lookup_request(req, ip).await;
+
+    match result {
+        Err(AuthError::Missing) | Ok(_) => {}
+        Err(e) => audit::auth_failure(ip, req.method(), req.uri().path(), e),
+    }
+
+    result
+}
+
+async fn lookup_request<B>(req: &Request<B>, ip: IpAddr) -> Result<Arc<TokenEntry>, AuthError> {
+    let token = request_token(req)?;
+
+    let store = TOKEN_STORE
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or(AuthError::InvalidToken)?;
+
+    // Only unknown tokens are limited, they are the expensive ones to check
+    let entry = store.find(token, auth_limiter(), &ip.to_string()).await?;
+
+    if entry.is_expired() {
+        return Err(AuthError::Expired);
+    }
+
+    Ok(entry)
+}
+
+/// Extracts the raw token from the `Authorization: Bearer <token>` header
+pub fn request_token<B>(req: &Request<B>) -> Result<&str, AuthError> {
+    let auth_header = req
+        .headers()
+        .get(AUTHORIZATION)
+        .ok_or(AuthError::Missing)?
+        .to_str()
+        .map_err(|_| AuthError::Malformed)?;
+
+    let (scheme, token) = auth_header.split_once(' ').ok_or(AuthError::Malformed)?;
+    let token = token.trim();
+    if !scheme.eq_ignore_ascii_case("Bearer") || token.is_empty() || token.contains(' ') {
+        return Err(AuthError::Malformed);
+    }
+
+    Ok(token)
+}
+
+/// Generates a new random token
+pub fn generate_token() -> Result<String, getrandom::Error> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes)?;
+
+    Ok(general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Hashes a token in the `sha256:<hex digest>` format of the tokens file
+pub fn hash_token(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    let hex: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+
+    format!("sha256:{hex}")
+}
+
+fn parse_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut digest = [0u8; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::WWW_AUTHENTICATE;
+
+    use super::*;
+    use crate::password::hash_password;
+    use crate::testing::*;
+
+    fn entry(options: &str) -> Arc<TokenEntry> {
+        let file = TokenFile::parse(&format!("token alice {options}")).unwrap();
+        let entry = file.entries().next().unwrap().entry.clone();
+
+        entry
+    }
+
+    fn parse_error(s: &str) -> String {
+        match TokenFile::parse(s) {
+            Ok(_) => panic!("{s:?} was parsed"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        assert_eq!(
+            parse_error("# comment\n\ntoken-without-user\n"),
+            "./.authorized_tokens:3: Expected a line of \"<token> <username> [key=value ...]\""
+        );
+        assert_eq!(
+            parse_error("a alice\nb bob max_ttl=forever"),
+            "./.authorized_tokens:2: Invalid value for max_ttl: forever"
+        );
+        assert_eq!(
+            parse_error("a alice colour=blue"),
+            "./.authorized_tokens:1: Unknown option \"colour\""
+        );
+        assert_eq!(
+            parse_error("a alice expires"),
+            "./.authorized_tokens:1: Expected key=value, found \"expires\""
+        );
+        assert_eq!(
+            parse_error("sha256:abc alice"),
+            "./.authorized_tokens:1: Invalid sha256 digest: abc"
+        );
+        assert!(parse_error("$argon2id$broken alice")
+            .starts_with("./.authorized_tokens:1: Invalid argon2 hash"));
+    }
+
+    #[test]
+    fn files_are_written_back_verbatim() {
+        let s = "# comment\n\ntoken  alice   scopes=upload\nsha256:{} bob\n";
+        let s = s.replace("{}", &"ab".repeat(32));
+        let file = TokenFile::parse(&s).unwrap();
+
+        assert_eq!(
+            file.to_string(),
+            s.replace("token  alice   scopes", "token alice scopes")
+        );
+        let kinds: Vec<_> = file.entries().map(|e| e.kind()).collect();
+        assert_eq!(kinds, ["plain", "sha256"]);
+    }
+
+    #[test]
+    fn hashed_tokens_are_found() {
+        let file = TokenFile::parse(&format!("{} alice\nplain bob", hash_token("secret"))).unwrap();
+        let store = TokenStore::new(&file);
+
+        assert_eq!(store.lookup("secret").unwrap().username, "alice");
+        assert_eq!(store.lookup("plain").unwrap().username, "bob");
+        assert!(store.lookup(&hash_token("secret")).is_none());
+        assert!(store.lookup("secre").is_none());
+    }
+
+    #[test]
+    fn scopes() {
+        let default = entry("");
+        assert!(default.has_scope(Scope::Upload));
+        assert!(default.has_scope(Scope::ReadPrivate));
+        assert!(default.has_scope(Scope::Delete));
+        assert!(!default.has_scope(Scope::Admin));
+
+        let upload_only = entry("scopes=upload");
+        assert!(upload_only.has_scope(Scope::Upload));
+        assert!(!upload_only.has_scope(Scope::Delete));
+
+        // Admins may do everything
+        let admin = entry("scopes=admin");
+        assert!(admin.has_scope(Scope::Upload));
+        assert!(admin.has_scope(Scope::ReadPrivate));
+        assert!(admin.has_scope(Scope::Delete));
+
+        assert!(TokenFile::parse("a alice scopes=upload,write").is_err());
+    }
+
+    #[test]
+    fn expiry() {
+        assert!(!entry("").is_expired());
+        assert!(entry("expires=1").is_expired());
+        assert!(!entry(&format!("expires={}", unix_time() + 60)).is_expired());
+    }
+
+    #[test]
+    fn content_types() {
+        let any = entry("");
+        assert!(any.allows_content_type("application/octet-stream"));
+
+        let restricted = entry("content_types=image/*,text/plain");
+        assert!(restricted.allows_content_type("image/png"));
+        assert!(restricted.allows_content_type("text/plain; charset=utf-8"));
+        assert!(restricted.allows_content_type("Text/Plain"));
+        assert!(!restricted.allows_content_type("text/html"));
+        assert!(!restricted.allows_content_type("application/pdf"));
+    }
+
+    #[test]
+    fn bearer_tokens() {
+        let token = |value: Option<&str>| {
+            let mut req = Request::builder();
+            if let Some(value) = value {
+                req = req.header(AUTHORIZATION, value);
+            }
+            request_token(&req.body(()).unwrap()).map(|t| t.to_owned())
+        };
+
+        assert_eq!(token(Some("Bearer abc")), Ok("abc".to_owned()));
+        assert_eq!(token(Some("bearer  abc ")), Ok("abc".to_owned()));
+        assert_eq!(token(None), Err(AuthError::Missing));
+        assert_eq!(token(Some("abc")), Err(AuthError::Malformed));
+        assert_eq!(token(Some("Basic abc")), Err(AuthError::Malformed));
+        assert_eq!(token(Some("Bearer ")), Err(AuthError::Malformed));
+        assert_eq!(token(Some("Bearer a b")), Err(AuthError::Malformed));
+    }
+
+    #[tokio::test]
+    async fn argon2_tokens_are_verified_once() {
+        let hash = hash_password("secret").await.unwrap();
+        let file = TokenFile::parse(&format!("plain alice\n{hash} bob\n")).unwrap();
+        let store = Arc::new(TokenStore::new(&file));
+        // Practically no refill, a single failed attempt is allowed
+        let limiter = RateLimiter::new(0.001, 1.0);
+        let find = |token| store.clone().find(token, &limiter, "ip");
+
+        assert_eq!(find("plain").await.unwrap().username, "alice");
+        assert!(store.lookup("secret").is_none());
+
+        // Valid tokens don't count as attempts
+        assert_eq!(find("secret").await.unwrap().username, "bob");
+        assert_eq!(store.lookup("secret").unwrap().username, "bob");
+
+        assert_eq!(find("wrong").await.unwrap_err(), AuthError::InvalidToken);
+        assert!(store.lookup("wrong").is_none());
+        // Rejected before verifying, known tokens still work
+        assert!(matches!(
+            find("secret2").await,
+            Err(AuthError::TooManyAttempts(_))
+        ));
+        assert_eq!(find("secret").await.unwrap().username, "bob");
+        assert_eq!(find("plain").await.unwrap().username, "alice");
+    }
+
+    #[tokio::test]
+    async fn requests_are_authenticated() {
+        let ip = new_ip();
+
+        let resp = get(ip, "/usage", Some("expired")).await;
+        assert_eq!(resp.status(), 401);
+        assert_eq!(
+            resp.headers()[WWW_AUTHENTICATE],
+            "Bearer realm=\"short_clip\", error=\"invalid_token\""
+        );
+
+        let resp = get(ip, "/usage", None).await;
+        assert_eq!(resp.status(), 401);
+        assert_eq!(
+            resp.headers()[WWW_AUTHENTICATE],
+            "Bearer realm=\"short_clip\""
+        );
+
+        // Unknown tokens are limited per client IP
+        for _ in 0..10 {
+            assert_eq!(get(ip, "/usage", Some("unknown")).await.status(), 401);
+        }
+        assert_eq!(get(ip, "/usage", Some("unknown")).await.status(), 429);
+        assert_eq!(get(ip, "/usage", Some("limited")).await.status(), 200);
+        assert_eq!(get(new_ip(), "/usage", Some("unknown")).await.status(), 401);
+    }
+}
@@ -1,99 +1,348 @@
-use hyper::body::Bytes;
-use hyper::header::{AUTHORIZATION, CONTENT_TYPE, LOCATION};
+use hyper::body::{Body, Bytes};
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
 use hyper::{Request, Response};
 
-use http_body_util::{BodyExt, Full};
-use shared::Metadata;
+use http_body_util::{BodyExt, Full, Limited};
+use shared::api::{routes, UploadOptions, UploadResponse};
+use shared::{Metadata, Visibility};
 
 use base64::{engine::general_purpose, Engine as _};
 use rustc_hash::FxHasher;
-use std::collections::HashMap;
-use std::hash::Hasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
 use std::sync::OnceLock;
-use tokio::fs;
+use tokio::sync::Mutex;
 
+use crate::db;
+use crate::password::hash_password;
+use crate::quota::user_usage;
+use crate::ratelimit::upload_limiter;
+use crate::storage::storage;
+use crate::tokens::{authenticate, Scope};
 use crate::util::*;
 
-static TOKENS_MAP: OnceLock<HashMap<String, String>> = OnceLock::new();
 static HOST: OnceLock<String> = OnceLock::new();
 
-pub async fn initialise_cells() -> Result<(), std::io::Error> {
-    let str = fs::read_to_string("./.authorized_tokens").await?;
-    let mut map = HashMap::new();
-
-    for line in str.lines() {
-        let (token, username) =line.split_once(" ").expect("Invalid file format for .authorized_tokens file. Values should be lines of \"<token> <username>\"");
-        map.insert(token.to_owned(), username.to_owned());
-    }
-
-    TOKENS_MAP.set(map).unwrap();
+/// Serializes quota checks with the writes they guard
+static UPLOAD_LOCK: Mutex<()> = Mutex::const_new(());
 
+pub fn initialise_cells() {
     if let Ok(host) = std::env::var("HOST") {
         HOST.set(host).unwrap();
     }
-
-    Ok(())
 }
 
-pub async fn handle_upload(
-    req: Request<hyper::body::Incoming>,
-) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>> {
-    let auth_header = match req.headers().get(AUTHORIZATION) {
-        Some(v) => v.to_str()?.to_owned(),
-        None => return Ok(bad_request("Missing Authorization header")),
+pub async fn handle_upload<B>(
+    req: Request<B>,
+    remote_addr: SocketAddr,
+) -> Result<Response<Full<Bytes>>, Box<dyn std::error::Error>>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Into<BoxError>,
+{
+    let entry = match authenticate(&req, client_ip(&req, remote_addr)).await {
+        Ok(e) => e,
+        Err(e) => return Ok(unauthorized(e)),
     };
-    let content_type = match req.headers().get(CONTENT_TYPE) {
-        Some(v) => v.to_str()?.to_owned(),
+    let username = &entry.username;
+
+    if !entry.has_scope(Scope::Upload) {
+        return Ok(forbidden("Token is not allowed to upload"));
+    }
+
+    let content_type = match req.headers().get(CONTENT_TYPE).map(|v| v.to_str()) {
+        Some(Ok(v)) => v.to_owned(),
+        Some(Err(_)) => return Ok(bad_request("Invalid Content-Type header")),
         None => return Ok(bad_request("Missing Content-Type header")),
     };
-    let ttl = match req.headers().get("ttl") {
-        Some(v) => Some(v.to_str()?.parse::<u64>()?),
+    // Options that aren't valid strings must not be mistaken for missing ones
+    if let Some(name) = UploadOptions::HEADERS.iter().find(|name| {
+        req.headers()
+            .get(**name)
+            .is_some_and(|v| v.to_str().is_err())
+    }) {
+        return Ok(bad_request(&format!("Invalid {name} header")));
+    }
+    let options = match UploadOptions::from_headers(|name| {
+        req.headers().get(name).and_then(|v| v.to_str().ok())
+    }) {
+        Ok(o) => o,
+        Err(e) => return Ok(bad_request(&e)),
+    };
+    let mut ttl = options.ttl;
+    let max_views = options.max_views;
+    let visibility = options.visibility.unwrap_or_default();
+
+    if !entry.allows_content_type(&content_type) {
+        return Ok(forbidden(&format!(
+            "Token is not allowed to upload {content_type}"
+        )));
+    }
+    if let Some(max_ttl) = entry.max_ttl {
+        match ttl {
+            Some(t) if t > max_ttl => {
+                return Ok(forbidden(&format!(
+                    "Token is not allowed to upload with a ttl above {max_ttl} seconds"
+                )))
+            }
+            // Clips of restricted tokens must not live forever
+            None => ttl = Some(max_ttl),
+            _ => {}
+        }
+    }
+
+    let Some(mut metadata) = Metadata::new(username, &content_type, ttl) else {
+        return Ok(bad_request("ttl is too large"));
+    };
+
+    if let Err(retry_after) = upload_limiter().check(username, 1.0) {
+        return Ok(too_many_requests(retry_after));
+    }
+
+    // Hashing is expensive, so it only happens for uploads that passed every check above
+    let password_hash = match &options.password {
+        Some(password) => Some(hash_password(password).await?),
         None => None,
     };
+    let quota = entry.quota.or_default();
 
-    // TODO: Implement actual authentication
-    let token_map = TOKENS_MAP.get().unwrap();
-    let token = auth_header.trim_start_matches("Bearer ");
-    let username = match token_map.get(token) {
-        Some(u) => u,
-        None => return Ok(auth_denied()),
+    // Reject oversized uploads before reading the body
+    let content_length = match req.headers().get(CONTENT_LENGTH) {
+        Some(v) => match v.to_str().ok().and_then(|v| v.parse::<u64>().ok()) {
+            Some(length) => Some(length),
+            None => return Ok(bad_request("Invalid Content-Length header")),
+        },
+        None => None,
     };
+    if let (Some(length), Some(max_upload)) = (content_length, quota.max_upload) {
+        if length > max_upload {
+            return Ok(payload_too_large(&format!(
+                "Quota exceeded: upload of {length} bytes is larger than the limit of {max_upload} bytes"
+            )));
+        }
+    }
 
     // Collect all of the data into single Bytes instance
     let frame_stream = req.into_body();
-    let data = frame_stream.collect().await?;
+    let data = match quota.max_upload {
+        Some(max_upload) => match Limited::new(frame_stream, max_upload as usize)
+            .collect()
+            .await
+        {
+            Ok(data) => data,
+            Err(_) => {
+                return Ok(payload_too_large(&format!(
+                    "Quota exceeded: upload is larger than the limit of {max_upload} bytes"
+                )))
+            }
+        },
+        None => match frame_stream.collect().await {
+            Ok(data) => data,
+            Err(e) => {
+                let e: BoxError = e.into();
+                return Err(e);
+            }
+        },
+    };
     let raw_data = &data.to_bytes()[..];
 
     // Hash the data
     let mut hasher = FxHasher::default();
+    // Uploads only share a clip if it is served and expires the same way for all of them
+    content_type.hash(&mut hasher);
+    visibility.as_str().hash(&mut hasher);
+    ttl.hash(&mut hasher);
+    // Restricted clips are only deduplicated per author, otherwise
+    // uploading the same data would reveal that a private clip exists
+    if visibility != Visibility::Public {
+        hasher.write(username.as_bytes());
+    }
+    // The salted hash gives every password protected upload its own id
+    if let Some(password_hash) = &password_hash {
+        hasher.write(password_hash.as_bytes());
+    }
+    // View limited clips can't be shared with other uploads, as they are deleted on their last view
+    if max_views.is_some() {
+        let mut nonce = [0u8; 16];
+        getrandom::fill(&mut nonce)?;
+        hasher.write(&nonce);
+    }
     hasher.write(raw_data);
     let hash = hasher.finish();
 
     // Create short alias for this data
     let id = general_purpose::URL_SAFE_NO_PAD.encode(hash.to_le_bytes());
 
-    let data_path = content_path().join(&id);
-    let metadata_path = data_path.with_extension("json");
+    // Only write file if it wasnt already saved, expired clips that weren't deleted yet are
+    // replaced. The lock keeps the usage read for the quota check valid until the clip is
+    // stored, but only within this process
+    let _guard = UPLOAD_LOCK.lock().await;
+    if db::get(&id).await?.is_none_or(|m| m.is_expired()) {
+        let usage = user_usage(username).await?;
+        if let Err(msg) = quota.check(&usage, raw_data.len() as u64) {
+            return Ok(payload_too_large(&msg));
+        }
 
-    // Only write file if it wasnt already saved
-    if !data_path.try_exists()? {
         // Save metadata to associate content type
-        let metadata = Metadata::new(username, &content_type, ttl);
-        fs::write(&data_path, raw_data).await?;
-        fs::write(&metadata_path, metadata.to_string()?).await?;
+        metadata.visibility = visibility;
+        metadata.password_hash = password_hash;
+        metadata.max_views = max_views;
+        metadata.encrypted = options.encrypted;
+        storage().put(&id, raw_data).await?;
+        db::insert(&id, &metadata, raw_data.len() as u64).await?;
     }
 
     // Return the newly cerated link depending on build
     let redirect = if let Some(host) = HOST.get() {
-        format!("https://{host}/{id}")
+        format!("https://{host}{}", routes::clip(&id))
     } else {
-        format!("http://localhost:3000/{id}")
+        format!("http://localhost:3000{}", routes::clip(&id))
     };
+    let body = serde_json::to_string(&UploadResponse {
+        id,
+        url: redirect.clone(),
+    })?;
 
     let resp = Response::builder()
         .status(201) // "Created" Status
         .header(LOCATION, &redirect)
-        .body(Full::new(Bytes::from(redirect)))?;
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)))?;
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+    use hyper::Method;
+    use shared::api::ClipInfo;
+
+    use super::*;
+    use crate::testing::*;
+
+    #[tokio::test]
+    async fn tokens_are_restricted() {
+        let ip = new_ip();
+
+        let resp = upload(ip, "reader", "no scope", &[]).await;
+        assert_eq!(resp.status(), 403);
+        assert!(text(resp).await.contains("not allowed to upload"));
+
+        let mut req = request(Method::POST, "/", Some("restricted"));
+        req = req.header(CONTENT_TYPE, "application/pdf");
+        let resp = send(ip, req.body(Full::new(Bytes::from("%PDF"))).unwrap()).await;
+        assert_eq!(resp.status(), 403);
+        assert!(text(resp)
+            .await
+            .contains("not allowed to upload application/pdf"));
+
+        let resp = upload(ip, "restricted", "too long", &[("ttl", "7200")]).await;
+        assert_eq!(resp.status(), 403);
+
+        // Restricted tokens get their maximum ttl instead of none at all
+        let id = uploaded_id(upload(ip, "restricted", "kept for an hour", &[]).await).await;
+        let resp = get(ip, &format!("/clips/{id}"), Some("restricted")).await;
+        let info: ClipInfo = serde_json::from_str(&text(resp).await).unwrap();
+        assert!(info.expires_at.unwrap().abs_diff(unix_time() + 3600) <= 1);
+
+        let id = uploaded_id(upload(ip, "restricted", "short", &[("ttl", "60")]).await).await;
+        let resp = get(ip, &format!("/clips/{id}"), Some("restricted")).await;
+        let info: ClipInfo = serde_json::from_str(&text(resp).await).unwrap();
+        assert!(info.expires_at.unwrap().abs_diff(unix_time() + 60) <= 1);
+    }
+
+    #[tokio::test]
+    async fn uploads_are_deduplicated() {
+        let ip = new_ip();
+        let id = uploaded_id(upload(ip, "uploader", "dedup", &[("ttl", "60")]).await).await;
+        assert_eq!(
+            id,
+            uploaded_id(upload(ip, "other", "dedup", &[("ttl", "60")]).await).await
+        );
+
+        // Every option that changes how the clip is served or when it expires gets its own clip
+        let mut ids = vec![id.clone()];
+        ids.push(uploaded_id(upload(ip, "uploader", "dedup", &[]).await).await);
+        ids.push(uploaded_id(upload(ip, "uploader", "dedup", &[("ttl", "61")]).await).await);
+        ids.push(
+            uploaded_id(
+                upload(
+                    ip,
+                    "uploader",
+                    "dedup",
+                    &[("ttl", "60"), ("visibility", "unlisted")],
+                )
+                .await,
+            )
+            .await,
+        );
+        let req = request(Method::POST, "/", Some("uploader"))
+            .header(CONTENT_TYPE, "text/html")
+            .header("ttl", "60")
+            .body(Full::new(Bytes::from("dedup")))
+            .unwrap();
+        ids.push(uploaded_id(send(ip, req).await).await);
+        for (i, id) in ids.iter().enumerate() {
+            assert!(!ids[i + 1..].contains(id), "{ids:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_clips_are_replaced() {
+        let ip = new_ip();
+        let id = uploaded_id(upload(ip, "uploader", "expiring", &[("ttl", "60")]).await).await;
+
+        // Expired clips stay around until someone tries to view them
+        let mut metadata = db::get(&id).await.unwrap().unwrap();
+        metadata.expires_at = Some(1);
+        db::update(&id, &metadata).await.unwrap();
 
-    return Ok(resp);
+        assert_eq!(
+            id,
+            uploaded_id(upload(ip, "other", "expiring", &[("ttl", "60")]).await).await
+        );
+        let metadata = db::get(&id).await.unwrap().unwrap();
+        assert!(!metadata.is_expired());
+        assert_eq!(metadata.author, "other");
+        assert_eq!(
+            text(get(ip, &format!("/{id}"), None).await).await,
+            "expiring"
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_headers_are_rejected() {
+        let ip = new_ip();
+        let upload_with = |name: &'static str, value: &'static [u8]| {
+            let mut req = request(Method::POST, "/", Some("other"))
+                .header(name, HeaderValue::from_bytes(value).unwrap())
+                .body(Full::new(Bytes::from("invalid header")))
+                .unwrap();
+            let headers = req.headers_mut();
+            if !headers.contains_key(CONTENT_TYPE) {
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+            }
+            send(ip, req)
+        };
+
+        for name in ["clip-password", "ttl", "visibility", "max-views"] {
+            let resp = upload_with(name, b"h\xc3\xbcnter2").await;
+            assert_eq!(resp.status(), 400, "{name}");
+            assert!(text(resp).await.contains(&format!("Invalid {name} header")));
+        }
+        assert_eq!(
+            upload_with("content-type", b"text/\xff").await.status(),
+            400
+        );
+        assert_eq!(upload_with("content-length", b"ten").await.status(), 400);
+        assert_eq!(upload_with("ttl", b"soon").await.status(), 400);
+        let resp = upload_with("ttl", b"18446744073709551615").await;
+        assert_eq!(resp.status(), 400);
+        assert!(text(resp).await.contains("ttl is too large"));
+
+        // Restricted tokens are turned away before their password is hashed
+        let resp = upload(ip, "reader", "no scope", &[("clip-password", "secret")]).await;
+        assert_eq!(resp.status(), 403);
+    }
 }
@@ -1,38 +1,137 @@
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use http_body_util::Full;
-use hyper::{body::Bytes, Response};
+use hyper::header::{CONTENT_TYPE, RETRY_AFTER, WWW_AUTHENTICATE};
+use hyper::http::response::Builder;
+use hyper::{body::Bytes, Request, Response};
+use shared::api::{ErrorBody, ErrorCode};
+pub use shared::parse_duration;
 
-pub fn bad_request(msg: &str) -> Response<Full<Bytes>> {
+use crate::tokens::AuthError;
+
+/// Error type of request bodies, handlers accept any body whose errors convert into it
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Builder of an error response, its body should be an `error_body` with the same code
+pub fn error_response(code: ErrorCode) -> Builder {
     Response::builder()
-        .status(400)
-        .body(Full::new(Bytes::from(msg.to_owned())))
-        .unwrap()
+        .status(code.status())
+        .header(CONTENT_TYPE, "application/json")
 }
 
-pub fn auth_denied() -> Response<Full<Bytes>> {
-    Response::builder()
-        .status(403)
-        .body(Full::new(Bytes::new()))
+/// Serializes the `ErrorBody` of an error response
+pub fn error_body(code: ErrorCode, msg: &str) -> Full<Bytes> {
+    let body = ErrorBody {
+        error: code,
+        message: msg.to_owned(),
+    };
+
+    Full::new(Bytes::from(
+        serde_json::to_string(&body).expect("Error bodies are always serializable"),
+    ))
+}
+
+fn error(code: ErrorCode, msg: &str) -> Response<Full<Bytes>> {
+    error_response(code).body(error_body(code, msg)).unwrap()
+}
+
+pub fn bad_request(msg: &str) -> Response<Full<Bytes>> {
+    error(ErrorCode::BadRequest, msg)
+}
+
+pub fn forbidden(msg: &str) -> Response<Full<Bytes>> {
+    error(ErrorCode::Forbidden, msg)
+}
+
+pub fn payload_too_large(msg: &str) -> Response<Full<Bytes>> {
+    error(ErrorCode::PayloadTooLarge, msg)
+}
+
+pub fn unauthorized(e: AuthError) -> Response<Full<Bytes>> {
+    if let AuthError::TooManyAttempts(retry_after) = e {
+        return too_many_requests(retry_after);
+    }
+
+    // Challenge as described in RFC 6750
+    let challenge = match e.error_code() {
+        Some(code) => format!("Bearer realm=\"short_clip\", error=\"{code}\""),
+        None => "Bearer realm=\"short_clip\"".to_owned(),
+    };
+    let msg = format!("Unauthorized: {e}");
+
+    error_response(ErrorCode::Unauthorized)
+        .header(WWW_AUTHENTICATE, challenge)
+        .body(error_body(ErrorCode::Unauthorized, &msg))
         .unwrap()
 }
 
 pub fn not_found() -> Response<Full<Bytes>> {
-    Response::builder()
-        .status(404)
-        .body(Full::new(Bytes::new()))
+    error(ErrorCode::NotFound, "Not found")
+}
+
+pub fn too_many_requests(retry_after: Duration) -> Response<Full<Bytes>> {
+    // Round up so clients never retry too early
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+    error_response(ErrorCode::TooManyRequests)
+        .header(RETRY_AFTER, seconds)
+        .body(error_body(ErrorCode::TooManyRequests, "Too Many Requests"))
         .unwrap()
 }
 
 pub fn internal_error(e: Box<dyn std::error::Error>) -> Response<Full<Bytes>> {
     eprintln!("{e}");
 
-    Response::builder()
-        .status(418)
-        .body(Full::new(Bytes::from("I'm a teapot")))
-        .unwrap()
+    error(ErrorCode::Internal, "Internal server error")
+}
+
+/// Extracts the clip id from a request path like `/<id>`
+///
+/// Returns `None` if the path can't be a valid id, which also prevents path traversal
+pub fn clip_id(path: &str) -> Option<&str> {
+    let id = path.strip_prefix('/')?;
+    let valid = !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    valid.then_some(id)
 }
 
 pub fn content_path() -> PathBuf {
-    PathBuf::from(std::env::current_dir().expect("Unable to get CWD")).join("contents")
+    std::env::current_dir()
+        .expect("Unable to get CWD")
+        .join("contents")
+}
+
+/// Determines the IP of the client that sent a request
+///
+/// If `TRUST_PROXY` is set the address appended to `X-Forwarded-For`
+/// by the reverse proxy is used instead of the peer address
+pub fn client_ip<B>(req: &Request<B>, remote_addr: SocketAddr) -> IpAddr {
+    if std::env::var_os("TRUST_PROXY").is_some() {
+        let forwarded = req
+            .headers()
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .last()
+            .and_then(|ip| ip.trim().parse().ok());
+
+        if let Some(ip) = forwarded {
+            return ip;
+        }
+    }
+
+    remote_addr.ip()
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
 }
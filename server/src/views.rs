@@ -0,0 +1,220 @@
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, USER_AGENT};
+use hyper::{Request, Response};
+
+use shared::Metadata;
+
+use crate::db;
+use crate::encrypted::KEEP_KEY_SCRIPT;
+use crate::storage::{storage, REWRITE_LOCK};
+
+/// User agents of link preview crawlers used by chat apps and social networks
+const PREVIEW_BOTS: &[&str] = &[
+    "bot",
+    "crawler",
+    "spider",
+    "preview",
+    "facebookexternalhit",
+    "whatsapp",
+    "skypeuripreview",
+    "embedly",
+    "vkshare",
+    "iframely",
+    "mattermost",
+    "slack",
+];
+
+/// Whether the request comes from a crawler that shouldn't use up a view
+pub fn is_preview_bot<B>(req: &Request<B>) -> bool {
+    let user_agent = match req.headers().get(USER_AGENT).and_then(|v| v.to_str().ok()) {
+        Some(ua) => ua.to_ascii_lowercase(),
+        None => return false,
+    };
+
+    PREVIEW_BOTS.iter().any(|bot| user_agent.contains(bot))
+}
+
+/// Counts a view of a clip with `max_views` and returns its data
+///
+/// The clip is deleted with its last view. Returns `None` if the clip
+/// is gone, e.g. because a concurrent request used up the last view
+pub async fn consume_view(
+    id: &str,
+) -> Result<Option<(Metadata, Vec<u8>)>, Box<dyn std::error::Error>> {
+    // Serializes view counting, so concurrent downloads can't exceed `max_views`
+    let _guard = REWRITE_LOCK.lock().await;
+
+    // Re-read the metadata while holding the lock to see the latest count
+    let mut metadata = match db::get(id).await? {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+    let max_views = match metadata.max_views {
+        Some(m) => m,
+        None => return Err(format!("Clip {id} has no view limit").into()),
+    };
+    if metadata.views >= max_views {
+        return Ok(None);
+    }
+
+    let data = match storage().get(id, None).await? {
+        Some(d) => d,
+        None => return Ok(None),
+    };
+    metadata.views += 1;
+
+    if metadata.views >= max_views {
+        db::delete(id).await?;
+        storage().delete(id).await?;
+    } else {
+        db::update(id, &metadata).await?;
+    }
+
+    Ok(Some((metadata, data)))
+}
+
+/// Page shown to browsers instead of the clip, so only an explicit click uses up a view
+pub fn reveal_page(id: &str, metadata: &Metadata) -> Response<Full<Bytes>> {
+    let remaining = metadata
+        .max_views
+        .unwrap_or_default()
+        .saturating_sub(metadata.views);
+    let notice = if remaining <= 1 {
+        "This clip will be deleted after it has been opened.".to_owned()
+    } else {
+        format!("This clip can be opened {remaining} more times.")
+    };
+
+    // `id` only consists of url safe base64 characters, so it can be embedded as is
+    let page = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Open clip</title>
+<style>
+body {{ font-family: sans-serif; display: flex; justify-content: center; margin-top: 20vh; }}
+</style>
+</head>
+<body>
+<form method="post" action="/{id}">
+<p>{notice}</p>
+<button type="submit">Open</button>
+</form>
+{KEEP_KEY_SCRIPT}
+</body>
+</html>
+"#
+    );
+
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(CACHE_CONTROL, "no-store")
+        .body(Full::new(Bytes::from(page)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::ACCEPT;
+    use hyper::Method;
+
+    use super::*;
+    use crate::testing::*;
+
+    fn view(path: &str, method: Method, headers: &[(&str, &str)]) -> Request<Full<Bytes>> {
+        let mut req = request(method, path, None);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+
+        req.body(Full::default()).unwrap()
+    }
+
+    #[test]
+    fn preview_bots() {
+        let bot = |user_agent: &str| {
+            is_preview_bot(&view("/", Method::GET, &[("user-agent", user_agent)]))
+        };
+
+        assert!(bot(
+            "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)"
+        ));
+        assert!(bot("WhatsApp/2.23.20.0"));
+        assert!(bot("facebookexternalhit/1.1"));
+        assert!(!bot(
+            "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0"
+        ));
+        assert!(!is_preview_bot(&view("/", Method::GET, &[])));
+    }
+
+    #[tokio::test]
+    async fn clips_are_deleted_with_their_last_view() {
+        let ip = new_ip();
+        let id =
+            uploaded_id(upload(ip, "burner", "burn after reading", &[("max-views", "2")]).await)
+                .await;
+        let path = format!("/{id}");
+
+        // Browsers and link previews only get a page to reveal the clip
+        let resp = send(
+            ip,
+            view(&path, Method::GET, &[(ACCEPT.as_str(), "text/html")]),
+        )
+        .await;
+        assert!(text(resp).await.contains("can be opened 2 more times"));
+        let resp = send(
+            ip,
+            view(&path, Method::GET, &[("user-agent", "Slackbot 1.0")]),
+        )
+        .await;
+        assert!(text(resp).await.contains("<form method=\"post\""));
+
+        let resp = send(ip, view(&path, Method::GET, &[])).await;
+        assert_eq!(text(resp).await, "burn after reading");
+
+        let resp = send(
+            ip,
+            view(&path, Method::GET, &[(ACCEPT.as_str(), "text/html")]),
+        )
+        .await;
+        assert!(text(resp)
+            .await
+            .contains("will be deleted after it has been opened"));
+        // The button of the page posts back to the clip
+        let resp = send(
+            ip,
+            view(&path, Method::POST, &[(ACCEPT.as_str(), "text/html")]),
+        )
+        .await;
+        assert_eq!(text(resp).await, "burn after reading");
+
+        assert_eq!(send(ip, view(&path, Method::GET, &[])).await.status(), 404);
+        assert!(db::get(&id).await.unwrap().is_none());
+        assert!(storage().head(&id).await.unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_views_respect_the_limit() {
+        let ip = new_ip();
+        let id = uploaded_id(upload(ip, "burner", "only once", &[("max-views", "1")]).await).await;
+        let path = format!("/{id}");
+
+        let views: Vec<_> = (0..8)
+            .map(|_| tokio::spawn(send(ip, view(&path, Method::GET, &[]))))
+            .collect();
+        let mut served = 0;
+        for resp in views {
+            match resp.await.unwrap().status().as_u16() {
+                200 => served += 1,
+                404 => {}
+                status => panic!("Unexpected status {status}"),
+            }
+        }
+
+        assert_eq!(served, 1);
+    }
+}
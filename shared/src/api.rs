@@ -0,0 +1,275 @@
+//! The HTTP API between client and server
+//!
+//! Everything both sides have to agree on lives here, so they can't drift apart.
+//! Breaking changes require a new `API_VERSION`
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Metadata, Visibility};
+
+/// Version of the API described by this module
+///
+/// Clients send it in the `short-clip-api-version` header, servers reject versions they don't know
+pub const API_VERSION: u8 = 1;
+
+/// Content type of clips containing a url, downloading them redirects to the url
+pub const URI_LIST: &str = "text/uri-list";
+
+/// The only supported end-to-end encryption scheme, AES-256-GCM with the nonce prepended
+pub const ENCRYPTION_SCHEME: &str = "aes-256-gcm";
+
+pub mod routes {
+    /// `POST` uploads a clip, answered with `201 Created` and an `UploadResponse`
+    pub const UPLOAD: &str = "/";
+    /// `GET` returns the `UsageResponse` of the authenticated user
+    pub const USAGE: &str = "/usage";
+    /// `GET` returns a `ClipList` of the clips uploaded by the authenticated user
+    pub const CLIPS: &str = "/clips";
+    pub const HEALTHZ: &str = "/healthz";
+    pub const READYZ: &str = "/readyz";
+
+    /// `GET` downloads a clip, `DELETE` deletes it
+    pub fn clip(id: &str) -> String {
+        format!("/{id}")
+    }
+
+    /// `GET` returns the `ClipInfo` of a clip
+    pub fn clip_info(id: &str) -> String {
+        format!("{CLIPS}/{id}")
+    }
+}
+
+pub mod headers {
+    pub const API_VERSION: &str = "short-clip-api-version";
+    /// Seconds until an uploaded clip expires
+    pub const TTL: &str = "ttl";
+    /// `public`, `unlisted` or `private`
+    pub const VISIBILITY: &str = "visibility";
+    /// Number of downloads after which a clip is deleted
+    pub const MAX_VIEWS: &str = "max-views";
+    /// Password protecting a clip, sent both on upload and download
+    pub const PASSWORD: &str = "clip-password";
+    /// Marks an upload as end-to-end encrypted, the value is the `ENCRYPTION_SCHEME`
+    pub const ENCRYPTION: &str = "clip-encryption";
+}
+
+/// Options of an upload, sent as headers next to the `Content-Type` of the clip
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct UploadOptions {
+    pub ttl: Option<u64>,
+    pub visibility: Option<Visibility>,
+    pub max_views: Option<u32>,
+    pub password: Option<String>,
+    /// The data was encrypted with the `ENCRYPTION_SCHEME` by the client
+    pub encrypted: bool,
+}
+
+/// Leaves out the password, so options can be logged
+impl fmt::Debug for UploadOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UploadOptions")
+            .field("ttl", &self.ttl)
+            .field("visibility", &self.visibility)
+            .field("max_views", &self.max_views)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("encrypted", &self.encrypted)
+            .finish()
+    }
+}
+
+impl UploadOptions {
+    /// Names of the headers describing upload options
+    pub const HEADERS: &'static [&'static str] = &[
+        headers::TTL,
+        headers::VISIBILITY,
+        headers::MAX_VIEWS,
+        headers::PASSWORD,
+        headers::ENCRYPTION,
+    ];
+
+    /// The headers describing these options
+    pub fn to_headers(&self) -> Vec<(&'static str, String)> {
+        let mut result = Vec::new();
+
+        if let Some(ttl) = self.ttl {
+            result.push((headers::TTL, ttl.to_string()));
+        }
+        if let Some(visibility) = self.visibility {
+            result.push((headers::VISIBILITY, visibility.as_str().to_owned()));
+        }
+        if let Some(max_views) = self.max_views {
+            result.push((headers::MAX_VIEWS, max_views.to_string()));
+        }
+        if let Some(password) = &self.password {
+            result.push((headers::PASSWORD, password.clone()));
+        }
+        if self.encrypted {
+            result.push((headers::ENCRYPTION, ENCRYPTION_SCHEME.to_owned()));
+        }
+
+        result
+    }
+
+    /// Parses the options from request headers, `header` looks up a header by name
+    pub fn from_headers<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Result<Self, String> {
+        let ttl = match header(headers::TTL) {
+            Some(v) => Some(v.parse().map_err(|_| "ttl must be a number of seconds")?),
+            None => None,
+        };
+        let visibility = header(headers::VISIBILITY).map(|v| v.parse()).transpose()?;
+        let max_views = match header(headers::MAX_VIEWS) {
+            Some(v) => match v.parse::<u32>() {
+                Ok(0) | Err(_) => return Err("max-views must be a positive number".to_owned()),
+                Ok(v) => Some(v),
+            },
+            None => None,
+        };
+        let encrypted = match header(headers::ENCRYPTION) {
+            Some(ENCRYPTION_SCHEME) => true,
+            Some(_) => return Err("Unsupported clip-encryption scheme".to_owned()),
+            None => false,
+        };
+
+        Ok(Self {
+            ttl,
+            visibility,
+            max_views,
+            password: header(headers::PASSWORD).map(|p| p.to_owned()),
+            encrypted,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadResponse {
+    pub id: String,
+    /// Link to the clip, also sent in the `Location` header
+    pub url: String,
+}
+
+/// Information about a clip, only available to its author and admins
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClipInfo {
+    pub id: String,
+    pub author: String,
+    pub content_type: String,
+    /// Size of the stored data in bytes
+    pub size: u64,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub visibility: Visibility,
+    pub password_protected: bool,
+    pub max_views: Option<u32>,
+    pub views: u32,
+    pub encrypted: bool,
+}
+
+impl ClipInfo {
+    pub fn new(id: &str, metadata: &Metadata, size: u64) -> Self {
+        Self {
+            id: id.to_owned(),
+            author: metadata.author.clone(),
+            content_type: metadata.content_type.clone(),
+            size,
+            created_at: metadata.created_at,
+            expires_at: metadata.expires_at,
+            visibility: metadata.visibility,
+            password_protected: metadata.password_hash.is_some(),
+            max_views: metadata.max_views,
+            views: metadata.views,
+            encrypted: metadata.encrypted,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClipList {
+    /// Oldest clips first
+    pub clips: Vec<ClipInfo>,
+}
+
+/// Storage consumed by a user, expired clips are not counted
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub bytes: u64,
+    pub clips: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UsageResponse {
+    pub user: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+    pub max_bytes: Option<u64>,
+    pub max_clips: Option<u64>,
+    pub max_upload: Option<u64>,
+}
+
+/// Usage of every user, returned by `/usage?all` for admins
+pub type AllUsageResponse = HashMap<String, Usage>;
+
+/// Body of every error response
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorBody {
+    pub error: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    PayloadTooLarge,
+    RangeNotSatisfiable,
+    TooManyRequests,
+    /// The clip is password protected, send the password in the `clip-password` header
+    PasswordRequired,
+    WrongPassword,
+    UnsupportedApiVersion,
+    Internal,
+    /// Codes added by newer servers
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    /// Status code of responses with this error
+    pub fn status(&self) -> u16 {
+        match self {
+            ErrorCode::BadRequest | ErrorCode::UnsupportedApiVersion => 400,
+            ErrorCode::Unauthorized | ErrorCode::PasswordRequired | ErrorCode::WrongPassword => 401,
+            ErrorCode::Forbidden => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::PayloadTooLarge => 413,
+            ErrorCode::RangeNotSatisfiable => 416,
+            ErrorCode::TooManyRequests => 429,
+            ErrorCode::Internal | ErrorCode::Unknown => 500,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_output_hides_the_password() {
+        let options = UploadOptions {
+            ttl: Some(60),
+            password: Some("hunter2".to_owned()),
+            ..Default::default()
+        };
+
+        let debug = format!("{options:?}");
+        assert!(debug.contains("ttl: Some(60)"));
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains("hunter2"));
+        assert!(format!("{:?}", UploadOptions::default()).contains("password: None"));
+    }
+}
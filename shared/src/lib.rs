@@ -1,14 +1,72 @@
+use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use serde::de::Error as _;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+pub mod api;
+mod migrations;
+
+use migrations::MetadataV1;
+
+/// Version of the metadata format written by this build
+pub const METADATA_VERSION: u8 = 1;
+
+/// Metadata of a clip in the latest format
+///
+/// It is only deserialized through `from_slice` and `from_str`, which upgrade older versions
+#[derive(Serialize)]
 pub struct Metadata {
     pub version: u8,
     pub created_at: u64,
     pub expires_at: Option<u64>,
     pub author: String,
     pub content_type: String,
+    pub visibility: Visibility,
+    /// Salted hash of the password needed to view the clip
+    pub password_hash: Option<String>,
+    /// The clip is deleted after it has been viewed this many times
+    pub max_views: Option<u32>,
+    /// How often a clip with `max_views` has been viewed
+    pub views: u32,
+    /// The data was encrypted by the client, the server never sees the key
+    pub encrypted: bool,
+}
+
+/// Who is able to view a clip
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Anyone with the link
+    #[default]
+    Public,
+    /// Anyone with the link, but search engines are asked not to index it
+    Unlisted,
+    /// Only the author and members of their teams
+    Private,
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::Unlisted => "unlisted",
+            Visibility::Private => "private",
+        }
+    }
+}
+
+impl FromStr for Visibility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "public" => Ok(Visibility::Public),
+            "unlisted" => Ok(Visibility::Unlisted),
+            "private" => Ok(Visibility::Private),
+            _ => Err(format!("Unknown visibility \"{s}\"")),
+        }
+    }
 }
 
 impl Metadata {
@@ -16,20 +74,30 @@ impl Metadata {
     ///
     /// `content_type` should be a valid mime type
     ///
-    /// `ttl` should be supplied in seconds
-    pub fn new(author: &str, content_type: &str, ttl: Option<u64>) -> Self {
+    /// `ttl` should be supplied in seconds, `None` is returned if the clip would expire
+    /// after the end of time
+    pub fn new(author: &str, content_type: &str, ttl: Option<u64>) -> Option<Self> {
         let start = SystemTime::now();
         let since_the_epoch = start
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards");
+        let expires_at = match ttl {
+            Some(t) => Some(since_the_epoch.as_secs().checked_add(t)?),
+            None => None,
+        };
 
-        Self {
-            version: 1,
+        Some(Self {
+            version: METADATA_VERSION,
             created_at: since_the_epoch.as_secs(),
-            expires_at: ttl.map(|t| since_the_epoch.as_secs() + t),
+            expires_at,
             author: author.to_owned(),
             content_type: content_type.to_owned(),
-        }
+            visibility: Visibility::default(),
+            password_hash: None,
+            max_views: None,
+            views: 0,
+            encrypted: false,
+        })
     }
 
     pub fn is_expired(&self) -> bool {
@@ -44,15 +112,76 @@ impl Metadata {
         }
     }
 
-    pub fn from_str(s: &str) -> Result<Metadata, serde_json::Error> {
-        serde_json::from_str(s)
+    /// Parses metadata of any known version, upgrading it to the latest one
+    pub fn from_slice(s: &[u8]) -> Result<Metadata, serde_json::Error> {
+        Self::from_value(serde_json::from_slice(s)?)
     }
 
-    pub fn from_slice(s: &[u8]) -> Result<Metadata, serde_json::Error> {
-        serde_json::from_slice(s)
+    /// Version of serialized metadata without parsing all of it
+    pub fn stored_version(s: &[u8]) -> Result<u8, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u8,
+        }
+
+        Ok(serde_json::from_slice::<Versioned>(s)?.version)
+    }
+
+    fn from_value(value: serde_json::Value) -> Result<Metadata, serde_json::Error> {
+        let version = value
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| serde_json::Error::missing_field("version"))?;
+
+        match version {
+            1 => Ok(MetadataV1::deserialize(value)?.into()),
+            v => Err(serde_json::Error::custom(format!(
+                "Unsupported metadata version {v}, it was written by a newer version"
+            ))),
+        }
     }
 
     pub fn to_string(&self) -> Result<String, serde_json::Error> {
         serde_json::to_string(self)
     }
 }
+
+impl FromStr for Metadata {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Metadata, serde_json::Error> {
+        Self::from_value(serde_json::from_str(s)?)
+    }
+}
+
+/// Parses a duration in seconds with an optional `s`, `m`, `h`, `d` or `w` suffix
+pub fn parse_duration(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last()? {
+        (i, 's') => (&s[..i], 1),
+        (i, 'm') => (&s[..i], 60),
+        (i, 'h') => (&s[..i], 60 * 60),
+        (i, 'd') => (&s[..i], 24 * 60 * 60),
+        (i, 'w') => (&s[..i], 7 * 24 * 60 * 60),
+        _ => (s, 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiry_must_not_overflow() {
+        let m = Metadata::new("alice", "text/plain", Some(60)).unwrap();
+        assert_eq!(m.expires_at, Some(m.created_at + 60));
+        assert!(Metadata::new("alice", "text/plain", None)
+            .unwrap()
+            .expires_at
+            .is_none());
+
+        assert!(Metadata::new("alice", "text/plain", Some(u64::MAX)).is_none());
+    }
+}
@@ -0,0 +1,116 @@
+//! Older versions of the metadata format and how they are upgraded
+//!
+//! Every version has its own `MetadataV<n>` struct with a conversion into the next one,
+//! so stored metadata is upgraded step by step until it reaches the latest version.
+//! When the format changes, add a struct for the new version and convert the latest one
+//! into `Metadata` instead
+
+use serde::Deserialize;
+
+use crate::{Metadata, Visibility, METADATA_VERSION};
+
+/// First version, fields were added over time and are missing in older files
+#[derive(Deserialize)]
+pub struct MetadataV1 {
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub author: String,
+    pub content_type: String,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub max_views: Option<u32>,
+    #[serde(default)]
+    pub views: u32,
+    #[serde(default)]
+    pub encrypted: bool,
+}
+
+/// Version 1 is the latest one
+impl From<MetadataV1> for Metadata {
+    fn from(v1: MetadataV1) -> Self {
+        Self {
+            version: METADATA_VERSION,
+            created_at: v1.created_at,
+            expires_at: v1.expires_at,
+            author: v1.author,
+            content_type: v1.content_type,
+            visibility: v1.visibility,
+            password_hash: v1.password_hash,
+            max_views: v1.max_views,
+            views: v1.views,
+            encrypted: v1.encrypted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::{Metadata, Visibility, METADATA_VERSION};
+
+    fn parse(json: &str) -> Result<Metadata, serde_json::Error> {
+        Metadata::from_value(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn v1_without_optional_fields() {
+        let m = parse(
+            r#"{"version":1,"created_at":10,"expires_at":null,"author":"alice","content_type":"text/plain"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(m.version, METADATA_VERSION);
+        assert_eq!(m.created_at, 10);
+        assert_eq!(m.expires_at, None);
+        assert_eq!(m.author, "alice");
+        assert_eq!(m.content_type, "text/plain");
+        assert_eq!(m.visibility, Visibility::Public);
+        assert_eq!(m.password_hash, None);
+        assert_eq!(m.max_views, None);
+        assert_eq!(m.views, 0);
+        assert!(!m.encrypted);
+    }
+
+    #[test]
+    fn v1_with_all_fields() {
+        let m = parse(
+            r#"{"version":1,"created_at":10,"expires_at":20,"author":"bob","content_type":"image/png",
+            "visibility":"private","password_hash":"hash","max_views":3,"views":1,"encrypted":true}"#,
+        )
+        .unwrap();
+
+        assert_eq!(m.version, METADATA_VERSION);
+        assert_eq!(m.expires_at, Some(20));
+        assert_eq!(m.visibility, Visibility::Private);
+        assert_eq!(m.password_hash.as_deref(), Some("hash"));
+        assert_eq!(m.max_views, Some(3));
+        assert_eq!(m.views, 1);
+        assert!(m.encrypted);
+    }
+
+    #[test]
+    fn metadata_is_written_in_the_latest_version() {
+        let mut m = Metadata::new("carl", "text/uri-list", None).unwrap();
+        m.visibility = Visibility::Unlisted;
+        let json = m.to_string().unwrap();
+
+        assert_eq!(
+            Metadata::stored_version(json.as_bytes()).unwrap(),
+            METADATA_VERSION
+        );
+        let m = Metadata::from_str(&json).unwrap();
+        assert_eq!(m.author, "carl");
+        assert_eq!(m.content_type, "text/uri-list");
+        assert_eq!(m.visibility, Visibility::Unlisted);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        assert!(parse(r#"{"created_at":10}"#).is_err());
+        assert!(parse(r#"{"version":2,"created_at":10}"#).is_err());
+    }
+}